@@ -0,0 +1,310 @@
+// A model of the battery powering the motors.
+//
+// The raw voltage the board reports sags whenever the
+// motors draw current, and is noisy on top of that.
+// Compensating for the internal resistance and low
+// pass filtering gives a voltage close to the resting
+// one, which is mapped onto a discharge curve for the
+// state of charge, and compared against the warn and
+// cutoff levels of the chemistry.
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chemistry
+{
+    LiPo,
+    NiMH,
+    LiFePO4,
+}
+
+// Per cell characteristics of a chemistry. All
+// voltages are resting voltages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Profile
+{
+    pub warn: f32,
+    pub cutoff: f32,
+    // How far above a level the voltage has to
+    // recover before the level is left again.
+    pub hysteresis: f32,
+    // In Ohm
+    pub internal_resistance: f32,
+    // Pairs of voltage and state of charge in percent,
+    // ordered by voltage.
+    pub curve: &'static [(f32, f32)],
+}
+
+const LIPO_CURVE: [(f32, f32); 7] = [
+    (3.30, 0.0), (3.60, 10.0), (3.70, 30.0), (3.75, 50.0), (3.85, 70.0), (4.00, 90.0), (4.20, 100.0),
+];
+
+const NIMH_CURVE: [(f32, f32); 7] = [
+    (1.00, 0.0), (1.10, 10.0), (1.18, 30.0), (1.22, 50.0), (1.26, 70.0), (1.30, 90.0), (1.40, 100.0),
+];
+
+const LIFEPO4_CURVE: [(f32, f32); 7] = [
+    (2.80, 0.0), (3.00, 10.0), (3.20, 30.0), (3.25, 50.0), (3.30, 70.0), (3.35, 90.0), (3.60, 100.0),
+];
+
+// Below this, the robot is considered idle and
+// no runtime is estimated.
+const MIN_RUNTIME_CURRENT: f32 = 0.1;
+
+impl Chemistry {
+
+    pub fn profile(&self) -> Profile
+    {
+        match self {
+            Chemistry::LiPo => Profile{
+                warn: 3.6,
+                cutoff: 3.3,
+                hysteresis: 0.1,
+                internal_resistance: 0.01,
+                curve: &LIPO_CURVE,
+            },
+            Chemistry::NiMH => Profile{
+                warn: 1.1,
+                cutoff: 1.0,
+                hysteresis: 0.05,
+                internal_resistance: 0.02,
+                curve: &NIMH_CURVE,
+            },
+            Chemistry::LiFePO4 => Profile{
+                warn: 3.0,
+                cutoff: 2.8,
+                hysteresis: 0.1,
+                internal_resistance: 0.01,
+                curve: &LIFEPO4_CURVE,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatterySettings
+{
+    pub chemistry: Chemistry,
+    pub cells: u8,
+    // In Ampere hours, needed for the runtime estimate
+    pub capacity: Option<f32>,
+    // Of the whole pack, in Ohm
+    pub internal_resistance: f32,
+    // Time constant of the low pass filter
+    pub filter: Duration,
+}
+
+impl BatterySettings {
+
+    pub fn new(chemistry: Chemistry, cells: u8) -> BatterySettings
+    {
+        BatterySettings{
+            chemistry,
+            cells,
+            capacity: None,
+            internal_resistance: chemistry.profile().internal_resistance * cells as f32,
+            filter: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Level
+{
+    Normal,
+    Warning,
+    // The motors must not be driven anymore
+    Cutoff,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryStatus
+{
+    // Filtered and compensated for sag, whole pack
+    pub voltage: f32,
+    pub cell_voltage: f32,
+    pub level: Level,
+    // In percent
+    pub state_of_charge: f32,
+    // Only known with a capacity and the
+    // robot drawing current.
+    pub remaining: Option<Duration>,
+}
+
+pub struct Battery
+{
+    settings: BatterySettings,
+    profile: Profile,
+    // The filtered values, None before the first update
+    voltage: Option<f32>,
+    current: f32,
+    when: Option<Instant>,
+    level: Level,
+}
+
+impl Battery {
+
+    pub fn new(settings: BatterySettings) -> Battery
+    {
+        Battery{
+            settings,
+            profile: settings.chemistry.profile(),
+            voltage: None,
+            current: 0.0,
+            when: None,
+            level: Level::Normal,
+        }
+    }
+
+    pub fn settings(&self) -> BatterySettings
+    {
+        self.settings
+    }
+
+    // Feed a measurement of the pack voltage and the
+    // total current drawn from it in Ampere.
+    pub fn update(&mut self, voltage: f32, current: f32, when: Instant) -> BatteryStatus
+    {
+        let current = current.max(0.0);
+        let resting = voltage + current * self.settings.internal_resistance;
+        let alpha = match self.when {
+            Some(last) => {
+                let dt = when.saturating_duration_since(last).as_secs_f32();
+                let tau = self.settings.filter.as_secs_f32();
+                if dt + tau > 0.0 { dt / (dt + tau) } else { 1.0 }
+            },
+            None => 1.0,
+        };
+        let filtered = match self.voltage {
+            Some(previous) => previous + alpha * (resting - previous),
+            None => resting,
+        };
+        self.voltage = Some(filtered);
+        self.current += alpha * (current - self.current);
+        self.when = Some(when);
+
+        let cell_voltage = filtered / self.settings.cells as f32;
+        self.level = self.next_level(cell_voltage);
+        let state_of_charge = self.state_of_charge(cell_voltage);
+        let remaining = match self.settings.capacity {
+            Some(capacity) if self.current >= MIN_RUNTIME_CURRENT => {
+                let hours = state_of_charge / 100.0 * capacity / self.current;
+                Some(Duration::from_secs_f32(hours * 3600.0))
+            },
+            _ => None,
+        };
+        BatteryStatus{
+            voltage: filtered,
+            cell_voltage,
+            level: self.level,
+            state_of_charge,
+            remaining,
+        }
+    }
+
+    fn next_level(&self, cell_voltage: f32) -> Level
+    {
+        let Profile{warn, cutoff, hysteresis, ..} = self.profile;
+        let fresh = if cell_voltage < cutoff {
+            Level::Cutoff
+        } else if cell_voltage < warn {
+            Level::Warning
+        } else {
+            Level::Normal
+        };
+        // Leaving a level needs some headroom,
+        // entering a worse one doesn't.
+        match (self.level, fresh) {
+            (Level::Cutoff, Level::Warning) | (Level::Cutoff, Level::Normal) if cell_voltage < cutoff + hysteresis => Level::Cutoff,
+            (Level::Cutoff, Level::Normal) | (Level::Warning, Level::Normal) if cell_voltage < warn + hysteresis => Level::Warning,
+            (_, level) => level,
+        }
+    }
+
+    fn state_of_charge(&self, cell_voltage: f32) -> f32
+    {
+        let curve = self.profile.curve;
+        let (first, last) = (curve[0], curve[curve.len() - 1]);
+        if cell_voltage <= first.0 {
+            return first.1;
+        }
+        if cell_voltage >= last.0 {
+            return last.1;
+        }
+        let upper = curve.iter().position(|(voltage, _)| *voltage > cell_voltage).unwrap();
+        let (v0, soc0) = curve[upper - 1];
+        let (v1, soc1) = curve[upper];
+        soc0 + (soc1 - soc0) * (cell_voltage - v0) / (v1 - v0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn unfiltered(chemistry: Chemistry, cells: u8) -> Battery
+    {
+        let mut settings = BatterySettings::new(chemistry, cells);
+        settings.filter = Duration::from_secs(0);
+        settings.internal_resistance = 0.0;
+        Battery::new(settings)
+    }
+
+    #[test]
+    fn levels_have_hysteresis() {
+        let mut battery = unfiltered(Chemistry::LiPo, 3);
+        let now = Instant::now();
+        assert_eq!(battery.update(12.0, 0.0, now).level, Level::Normal);
+        assert_eq!(battery.update(10.5, 0.0, now).level, Level::Warning);
+        assert_eq!(battery.update(9.8, 0.0, now).level, Level::Cutoff);
+        // barely above the cutoff isn't enough
+        assert_eq!(battery.update(10.0, 0.0, now).level, Level::Cutoff);
+        assert_eq!(battery.update(10.3, 0.0, now).level, Level::Warning);
+        assert_eq!(battery.update(10.9, 0.0, now).level, Level::Warning);
+        assert_eq!(battery.update(11.2, 0.0, now).level, Level::Normal);
+    }
+
+    #[test]
+    fn state_of_charge_follows_curve() {
+        let mut battery = unfiltered(Chemistry::NiMH, 10);
+        let now = Instant::now();
+        assert_relative_eq!(battery.update(14.0, 0.0, now).state_of_charge, 100.0);
+        assert_relative_eq!(battery.update(12.2, 0.0, now).state_of_charge, 50.0);
+        assert_relative_eq!(battery.update(12.4, 0.0, now).state_of_charge, 60.0, epsilon = 1e-3);
+        assert_relative_eq!(battery.update(9.0, 0.0, now).state_of_charge, 0.0);
+    }
+
+    #[test]
+    fn sag_is_compensated_and_filtered() {
+        let mut settings = BatterySettings::new(Chemistry::LiFePO4, 4);
+        settings.internal_resistance = 0.1;
+        settings.filter = Duration::from_secs(1);
+        let mut battery = Battery::new(settings);
+        let start = Instant::now();
+        // 5A under load make for 0.5V of sag
+        let status = battery.update(12.5, 5.0, start);
+        assert_relative_eq!(status.voltage, 13.0);
+        assert_relative_eq!(status.cell_voltage, 3.25);
+        // a short dip is smoothed out
+        let status = battery.update(11.0, 0.0, start + Duration::from_secs(1));
+        assert_relative_eq!(status.voltage, 12.0);
+        assert_eq!(status.level, Level::Normal);
+    }
+
+    #[test]
+    fn runtime_estimate_needs_capacity_and_load() {
+        let mut settings = BatterySettings::new(Chemistry::LiPo, 3);
+        settings.internal_resistance = 0.0;
+        settings.filter = Duration::from_secs(0);
+        let mut battery = Battery::new(settings);
+        let now = Instant::now();
+        assert_eq!(battery.update(11.25, 2.0, now).remaining, None);
+
+        settings.capacity = Some(2.0);
+        let mut battery = Battery::new(settings);
+        // half charged, 1Ah left at 2A
+        let status = battery.update(11.25, 2.0, now);
+        assert_relative_eq!(status.remaining.unwrap().as_secs_f32(), 1800.0, epsilon = 1.0);
+        assert_eq!(battery.update(11.25, 0.0, now).remaining, None);
+    }
+}
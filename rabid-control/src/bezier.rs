@@ -0,0 +1,331 @@
+// Cubic Bézier curves.
+//
+// Course designers draw these in vector tools, so the
+// segment takes the four control points as drawn. As
+// all segments start at the origin heading along the
+// x-axis, the curve is moved and rotated so that its
+// start and starting tangent line up with that.
+//
+// The parameter of a Bézier curve doesn't progress
+// uniformly along it, but at expects the position as a
+// fraction of the length. So the arc length is tabulated
+// once, and positions are mapped back to the curve
+// parameter by searching the table and refining the
+// result with Newton's method.
+use crate::path::{PathSegment, SegmentSpec, Vector, Rotation};
+
+// The curve parameter is split into this
+// many intervals for the arc length table.
+const BEZIER_TABLE_SIZE: usize = 64;
+// Nodes and weights of the five point
+// Gauss-Legendre quadrature on [-1, 1]
+const GAUSS_LEGENDRE: [(f64, f64); 5] = [
+    (0.0, 0.5688888888888889),
+    (-0.5384693101056831, 0.4786286704993665),
+    (0.5384693101056831, 0.4786286704993665),
+    (-0.906179845938664, 0.2369268850561891),
+    (0.906179845938664, 0.2369268850561891),
+];
+const BEZIER_NEWTON_ITERATIONS: usize = 16;
+// in cm
+const BEZIER_TOLERANCE: f64 = 1e-9;
+
+pub struct BezierSegment
+{
+    points: [Vector; 4],
+    // The arc length from the start up to
+    // the parameter i / BEZIER_TABLE_SIZE
+    table: Vec<f64>,
+    start_heading: f64,
+}
+
+impl BezierSegment {
+    pub fn new(p0: Vector, p1: Vector, p2: Vector, p3: Vector) -> BezierSegment
+    {
+        let mut segment = BezierSegment{points: [p0, p1, p2, p3], table: vec![0.0], start_heading: 0.0};
+        segment.start_heading = segment.parameter_heading(0.0);
+        for index in 0..BEZIER_TABLE_SIZE {
+            let start = index as f64 / BEZIER_TABLE_SIZE as f64;
+            let end = (index + 1) as f64 / BEZIER_TABLE_SIZE as f64;
+            let length = segment.table[index] + segment.arc_length(start, end);
+            segment.table.push(length);
+        }
+        segment
+    }
+
+    fn point(&self, t: f64) -> Vector
+    {
+        let [p0, p1, p2, p3] = self.points;
+        let u = 1.0 - t;
+        p0 * (u * u * u) + p1 * (3.0 * u * u * t) + p2 * (3.0 * u * t * t) + p3 * (t * t * t)
+    }
+
+    fn derivative(&self, t: f64) -> Vector
+    {
+        let [p0, p1, p2, p3] = self.points;
+        let u = 1.0 - t;
+        (p1 - p0) * (3.0 * u * u) + (p2 - p1) * (6.0 * u * t) + (p3 - p2) * (3.0 * t * t)
+    }
+
+    fn second_derivative(&self, t: f64) -> Vector
+    {
+        let [p0, p1, p2, p3] = self.points;
+        (p2 - p1 * 2.0 + p0) * (6.0 * (1.0 - t)) + (p3 - p2 * 2.0 + p1) * (6.0 * t)
+    }
+
+    // Where the curve heads at parameter t. If control
+    // points coincide the derivative can vanish. At the
+    // ends the curve then leaves towards the next control
+    // point that differs, elsewhere the second derivative
+    // gives the direction.
+    fn parameter_heading(&self, t: f64) -> f64
+    {
+        let [p0, p1, p2, p3] = self.points;
+        let candidates = if t <= 0.0 {
+            [p1 - p0, p2 - p0, p3 - p0]
+        }
+        else if t >= 1.0
+        {
+            [p3 - p2, p3 - p1, p3 - p0]
+        }
+        else
+        {
+            [self.derivative(t), self.second_derivative(t), p3 - p0]
+        };
+        let tangent = candidates.iter()
+            .find(|tangent| tangent.norm() > 0.0)
+            .copied()
+            .unwrap_or_else(|| Vector::new(1.0, 0.0));
+        tangent[1].atan2(tangent[0])
+    }
+
+    fn arc_length(&self, start: f64, end: f64) -> f64
+    {
+        let half = (end - start) / 2.0;
+        let middle = (start + end) / 2.0;
+        GAUSS_LEGENDRE.iter().fold(0.0, |acc, (node, weight)| {
+            acc + weight * self.derivative(middle + half * node).norm()
+        }) * half
+    }
+
+    fn table_index(&self, t: f64) -> usize
+    {
+        ((t * BEZIER_TABLE_SIZE as f64) as usize).min(BEZIER_TABLE_SIZE - 1)
+    }
+
+    // The arc length from the start up to parameter t
+    fn length_at(&self, t: f64) -> f64
+    {
+        let index = self.table_index(t);
+        self.table[index] + self.arc_length(index as f64 / BEZIER_TABLE_SIZE as f64, t)
+    }
+
+    // The parameter at which the curve
+    // has covered the given distance.
+    fn parameter_at(&self, distance: f64) -> f64
+    {
+        let distance = distance.max(0.0).min(self.length());
+        let index = match self.table.binary_search_by(|length| length.total_cmp(&distance)) {
+            Ok(index) => index,
+            Err(index) => index.saturating_sub(1),
+        }.min(BEZIER_TABLE_SIZE - 1);
+        let mut low = index as f64 / BEZIER_TABLE_SIZE as f64;
+        let mut high = (index + 1) as f64 / BEZIER_TABLE_SIZE as f64;
+        let interval = self.table[index + 1] - self.table[index];
+        if interval <= 0.0 {
+            return low;
+        }
+        // start from linear interpolation inside the
+        // interval, and keep Newton inside it.
+        let mut t = low + (high - low) * (distance - self.table[index]) / interval;
+        for _ in 0..BEZIER_NEWTON_ITERATIONS {
+            let error = self.length_at(t) - distance;
+            if error.abs() < BEZIER_TOLERANCE {
+                break;
+            }
+            if error > 0.0 {
+                high = t;
+            }
+            else
+            {
+                low = t;
+            }
+            let speed = self.derivative(t).norm();
+            let next = t - error / speed;
+            t = if speed > 0.0 && low < next && next < high {
+                next
+            }
+            else
+            {
+                (low + high) / 2.0
+            };
+        }
+        t
+    }
+}
+
+impl PathSegment for BezierSegment
+{
+    fn length(&self) -> f64
+    {
+        self.table[BEZIER_TABLE_SIZE]
+    }
+
+    fn at(&self, position: f64) -> (Vector, Rotation)
+    {
+        let t = self.parameter_at(position * self.length());
+        let to_start = Rotation::new(-self.start_heading);
+        let pos = to_start.transform_vector(&(self.point(t) - self.points[0]));
+        (pos, Rotation::new(self.parameter_heading(t) - self.start_heading))
+    }
+
+    fn curvature(&self, position: f64) -> f64
+    {
+        let t = self.parameter_at(position * self.length());
+        let first = self.derivative(t);
+        let second = self.second_derivative(t);
+        let speed = first.norm();
+        if speed < BEZIER_TOLERANCE {
+            return 0.0;
+        }
+        (first[0] * second[1] - first[1] * second[0]) / (speed * speed * speed)
+    }
+
+    fn spec(&self) -> Option<SegmentSpec>
+    {
+        let mut points = [[0.0; 2]; 4];
+        for (point, control) in points.iter_mut().zip(self.points.iter()) {
+            *point = [control[0], control[1]];
+        }
+        Some(SegmentSpec::Bezier{points})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    // The usual approximation of a quarter circle
+    const KAPPA: f64 = 0.5522847498;
+
+    fn quarter_circle(radius: f64) -> BezierSegment
+    {
+        BezierSegment::new(
+            Vector::new(0.0, 0.0),
+            Vector::new(KAPPA * radius, 0.0),
+            Vector::new(radius, radius - KAPPA * radius),
+            Vector::new(radius, radius))
+    }
+
+    fn equal_eps(a: &Vector, b: &Vector, e: f64) -> bool
+    {
+        (b - a).norm() <= e
+    }
+
+    #[test]
+    fn positions_are_by_distance() {
+        // the control points bunch up at the start,
+        // so the parameter runs unevenly
+        let segment = BezierSegment::new(
+            Vector::new(0.0, 0.0), Vector::new(1.0, 0.0),
+            Vector::new(2.0, 0.0), Vector::new(10.0, 0.0));
+        assert!((segment.length() - 10.0).abs() < 1e-9);
+        for position in [0.0, 0.1, 0.5, 0.9, 1.0].iter() {
+            let (pos, rot) = segment.at(*position);
+            assert!(equal_eps(&pos, &Vector::new(position * 10.0, 0.0), 1e-8));
+            assert_eq!(rot.angle(), 0.0);
+        }
+    }
+
+    #[test]
+    fn arc_length_matches_polyline() {
+        let segment = quarter_circle(10.0);
+        let steps = 100000;
+        let mut length = 0.0;
+        for step in 0..steps {
+            let a = segment.point(step as f64 / steps as f64);
+            let b = segment.point((step + 1) as f64 / steps as f64);
+            length += (b - a).norm();
+        }
+        assert!((segment.length() - length).abs() < 1e-6);
+        // and it's close to the real quarter circle
+        assert!((segment.length() - PI * 5.0).abs() < 0.01);
+        for distance in [0.5, 3.0, 7.77, 15.0].iter() {
+            let t = segment.parameter_at(*distance);
+            assert!((segment.length_at(t) - distance).abs() < 1e-8);
+        }
+    }
+
+    #[test]
+    fn midpoint_of_symmetric_curve() {
+        let segment = quarter_circle(10.0);
+        let (pos, rot) = segment.at(0.5);
+        assert!(equal_eps(&pos, &segment.point(0.5), 1e-8));
+        assert!((rot.angle() - PI / 4.0).abs() < 1e-8);
+        let (pos, rot) = segment.at(1.0);
+        assert!(equal_eps(&pos, &Vector::new(10.0, 10.0), 1e-8));
+        assert!((rot.angle() - PI / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn curvature_of_quarter_circle() {
+        let segment = quarter_circle(10.0);
+        for position in [0.0, 0.25, 0.5, 1.0].iter() {
+            assert!((segment.curvature(*position) - 0.1).abs() < 0.005);
+        }
+        let line = BezierSegment::new(
+            Vector::new(0.0, 0.0), Vector::new(1.0, 0.0),
+            Vector::new(2.0, 0.0), Vector::new(10.0, 0.0));
+        assert_eq!(line.curvature(0.5), 0.0);
+    }
+
+    #[test]
+    fn curve_is_aligned_to_its_start() {
+        // the quarter circle, drawn somewhere else
+        // and heading down
+        let radius = 10.0;
+        let offset = Vector::new(50.0, 20.0);
+        let rotation = Rotation::new(-PI / 2.0);
+        let moved = |x: f64, y: f64| offset + rotation.transform_vector(&Vector::new(x, y));
+        let segment = BezierSegment::new(
+            moved(0.0, 0.0),
+            moved(KAPPA * radius, 0.0),
+            moved(radius, radius - KAPPA * radius),
+            moved(radius, radius));
+        let (pos, rot) = segment.at(1.0);
+        assert!(equal_eps(&pos, &Vector::new(10.0, 10.0), 1e-8));
+        assert!((rot.angle() - PI / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn non_finite_points_do_not_panic() {
+        let segment = BezierSegment::new(
+            Vector::new(0.0, 0.0), Vector::new(f64::NAN, 0.0),
+            Vector::new(2.0, 0.0), Vector::new(10.0, 0.0));
+        segment.at(0.5);
+        segment.curvature(0.5);
+    }
+
+    #[test]
+    fn coinciding_control_points() {
+        let segment = BezierSegment::new(
+            Vector::new(0.0, 0.0), Vector::new(0.0, 0.0),
+            Vector::new(0.0, 10.0), Vector::new(10.0, 10.0));
+        // the tangent at the start comes from the
+        // third point, so the curve is turned right
+        let (pos, rot) = segment.at(0.0);
+        assert_eq!(pos, Vector::new(0.0, 0.0));
+        assert_eq!(rot.angle(), 0.0);
+        let (pos, rot) = segment.at(1.0);
+        assert!(equal_eps(&pos, &Vector::new(10.0, -10.0), 1e-8));
+        // and leaves along the x-axis as drawn
+        assert!((rot.angle() + PI / 2.0).abs() < 1e-12);
+        // the same at the end
+        let segment = BezierSegment::new(
+            Vector::new(0.0, 0.0), Vector::new(10.0, 0.0),
+            Vector::new(10.0, 10.0), Vector::new(10.0, 10.0));
+        let (_, rot) = segment.at(1.0);
+        assert!((rot.angle() - PI / 2.0).abs() < 1e-12);
+    }
+}
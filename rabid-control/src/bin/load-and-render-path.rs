@@ -1,10 +1,71 @@
-use std::f64::consts::PI;
-use ::rr::path::{LinearSegment, CircleSegment};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use ::rr::path::{PathSegment, Vector};
+use ::rr::pathfile;
+use ::rr::render::{render_svg, Arena, RenderSettings};
+use ::rr::twowheel::{TwoWheelRobot, WHEELBASE, WHEELDIAMETER};
+
+const USAGE: &str = "usage: load-and-render-path <path file> [output.svg] [--arena minx,miny,maxx,maxy]";
+
+fn fail(message: &str) -> !
+{
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn parse_arena(text: &str) -> Option<Arena>
+{
+    let values: Vec<f64> = text.split(',').map(|value| value.trim().parse()).collect::<Result<_, _>>().ok()?;
+    match values[..] {
+        [minx, miny, maxx, maxy] if minx < maxx && miny < maxy =>
+            Some(Arena::new(Vector::new(minx, miny), Vector::new(maxx, maxy))),
+        _ => None,
+    }
+}
 
 fn main()
 {
-    let straight = LinearSegment::new(10.0);
-    println!("{}", serde_json::to_string(&straight).expect("json"));
-    let curve = CircleSegment::new(10.0, PI / 2.0);
-    println!("{}", serde_json::to_string(&curve).expect("json"));
+    let mut arguments = Vec::new();
+    let mut settings = RenderSettings::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--arena" {
+            let arena = args.next().and_then(|text| parse_arena(&text));
+            match arena {
+                Some(arena) => settings.arena = Some(arena),
+                None => fail("--arena needs minx,miny,maxx,maxy in cm"),
+            }
+        }
+        else
+        {
+            arguments.push(arg);
+        }
+    }
+    let (filename, output) = match &arguments[..] {
+        [filename] => (filename.clone(), None),
+        [filename, output] => (filename.clone(), Some(output.clone())),
+        _ => fail(USAGE),
+    };
+
+    let path = match pathfile::load(Path::new(&filename)) {
+        Ok(path) => path,
+        Err(error) => fail(&format!("{}: {}", filename, error)),
+    };
+    eprintln!("{}: {:.1}cm", filename, path.length());
+
+    let robot = TwoWheelRobot::new(WHEELBASE, WHEELDIAMETER);
+    let svg = match render_svg(&path, &robot, &settings) {
+        Ok(svg) => svg,
+        Err(error) => fail(&format!("{}: {}", filename, error)),
+    };
+    match output {
+        Some(output) => {
+            if let Err(error) = fs::write(&output, svg) {
+                fail(&format!("{}: {}", output, error));
+            }
+        },
+        None => print!("{}", svg),
+    }
 }
@@ -0,0 +1,57 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use ::rr::pathfile::{self, Format, PathFile};
+use ::rr::pathscript;
+
+const USAGE: &str = "usage:
+  rabid-path script '<statements>' [output]   parse a path script, print it as JSON or write it to output
+  rabid-path format <path file>               print a path file as a path script
+  rabid-path convert <input> <output>         convert between path file formats";
+
+fn fail(message: &str) -> !
+{
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn load(filename: &str) -> PathFile
+{
+    match pathfile::load(Path::new(filename)) {
+        Ok(path) => PathFile::from_path(&path).unwrap_or_else(|error| fail(&format!("{}: {}", filename, error))),
+        Err(error) => fail(&format!("{}: {}", filename, error)),
+    }
+}
+
+fn write(file: &PathFile, filename: Option<&str>)
+{
+    let format = match filename {
+        Some(filename) => Format::from_path(Path::new(filename)).unwrap_or_else(|error| fail(&format!("{}: {}", filename, error))),
+        None => Format::Json,
+    };
+    let text = file.to_string(format).unwrap_or_else(|error| fail(&error.to_string()));
+    match filename {
+        Some(filename) => {
+            if let Err(error) = fs::write(filename, text) {
+                fail(&format!("{}: {}", filename, error));
+            }
+        },
+        None => println!("{}", text),
+    }
+}
+
+fn main()
+{
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match &args[..] {
+        ["script", text] | ["script", text, _] => {
+            let segments = pathscript::parse_segments(text).unwrap_or_else(|error| fail(&error.to_string()));
+            write(&PathFile{segments}, args.get(2).copied());
+        },
+        ["format", filename] => print!("{}", pathscript::format(&load(filename).segments)),
+        ["convert", input, output] => write(&load(input), Some(*output)),
+        _ => fail(USAGE),
+    }
+}
@@ -0,0 +1,409 @@
+// Clothoids, also known as Euler spirals.
+//
+// Along a clothoid the curvature changes linearly
+// with the distance travelled. Placed between a
+// straight line and a circle, it lets the difference
+// of the wheel speeds build up gradually instead of
+// jumping, which keeps the wheels from slipping.
+//
+// The heading along the segment is a quadratic in
+// the distance, so the position is given by the
+// Fresnel integrals.
+extern crate nalgebra as na;
+use na::Complex;
+use std::f64::consts::PI;
+use serde::{Deserialize, Serialize};
+
+use crate::path::{PathSegment, CompoundPath, SegmentSpec, Vector, Rotation};
+
+// Below this the power series is used, above it the
+// asymptotic expansion. Around here both are good to
+// about 1e-9, and much better away from it.
+const FRESNEL_SERIES_LIMIT: f64 = 3.4;
+const FRESNEL_MAX_TERMS: usize = 200;
+// If the curvature changes less than this per cm^2,
+// the segment is treated as a circle, as the Fresnel
+// integrals would lose their precision.
+const CLOTHOID_MIN_SHARPNESS: f64 = 1e-12;
+
+// The Fresnel integrals C(x) and S(x), integrating
+// cos(pi/2 t^2) and sin(pi/2 t^2) from 0 to x.
+//
+// Both the power series and the asymptotic expansions
+// of the auxiliary functions f and g are taken from
+// Abramowitz and Stegun, Handbook of Mathematical
+// Functions, section 7.3.
+pub fn fresnel(x: f64) -> (f64, f64)
+{
+    let ax = x.abs();
+    let (c, s) = if ax <= FRESNEL_SERIES_LIMIT {
+        fresnel_series(ax)
+    }
+    else
+    {
+        fresnel_asymptotic(ax)
+    };
+    if x < 0.0 {
+        (-c, -s)
+    }
+    else
+    {
+        (c, s)
+    }
+}
+
+// The terms x (pi/2 x^2)^k / k! are shared by both
+// series. Divided by 2k + 1, the even ones add up to C
+// and the odd ones to S, with alternating signs.
+fn fresnel_series(x: f64) -> (f64, f64)
+{
+    let z = PI / 2.0 * x * x;
+    let mut term = x;
+    let mut c = x;
+    let mut s = 0.0;
+    for k in 1..FRESNEL_MAX_TERMS {
+        term *= z / k as f64;
+        let summand = term / (2 * k + 1) as f64;
+        match k % 4 {
+            0 => c += summand,
+            1 => s += summand,
+            2 => c -= summand,
+            _ => s -= summand,
+        }
+        if summand <= f64::EPSILON * c.abs().max(s.abs()) {
+            break;
+        }
+    }
+    (c, s)
+}
+
+// With y = pi x^2, pi x f(x) is 1 - 3/y^2 + 105/y^4 - ...
+// and pi x g(x) is 1/y - 15/y^3 + ..., so the terms
+// (2k - 1)!! / y^k take turns between the two. The
+// expansions diverge, and are cut off before the
+// terms start to grow again.
+fn fresnel_asymptotic(x: f64) -> (f64, f64)
+{
+    let y = PI * x * x;
+    let mut term = 1.0;
+    let mut f = 1.0;
+    let mut g = 0.0;
+    for k in 1..FRESNEL_MAX_TERMS {
+        let next = term * (2 * k - 1) as f64 / y;
+        if next >= term {
+            break;
+        }
+        term = next;
+        match k % 4 {
+            0 => f += term,
+            1 => g += term,
+            2 => f -= term,
+            _ => g -= term,
+        }
+        if term < f64::EPSILON {
+            break;
+        }
+    }
+    let f = f / (PI * x);
+    let g = g / (PI * x);
+    let (sin, cos) = (y / 2.0).sin_cos();
+    (0.5 + f * sin - g * cos, 0.5 - f * cos - g * sin)
+}
+
+// Curvature is given in 1/cm, positive
+// values turn left, like CircleSegment.
+#[derive(Serialize, Deserialize)]
+pub struct ClothoidSegment
+{
+    length: f64,
+    start_curvature: f64,
+    end_curvature: f64,
+}
+
+impl ClothoidSegment {
+    pub fn new(length: f64, start_curvature: f64, end_curvature: f64) -> ClothoidSegment
+    {
+        ClothoidSegment{length, start_curvature, end_curvature}
+    }
+
+    // How fast the curvature changes, in 1/cm^2. The
+    // heading after s cm is sharpness * s^2 + curvature * s.
+    fn sharpness(&self) -> f64
+    {
+        (self.end_curvature - self.start_curvature) / (2.0 * self.length)
+    }
+
+    // Integrates exp(i * heading) from 0 to s,
+    // which is the position after s cm.
+    fn integrate(&self, s: f64) -> Complex<f64>
+    {
+        let a = self.sharpness();
+        let b = self.start_curvature;
+        if a.abs() < CLOTHOID_MIN_SHARPNESS {
+            if b == 0.0 {
+                return Complex::new(s, 0.0);
+            }
+            // a circle
+            return (Complex::new((b * s).cos(), (b * s).sin()) - Complex::new(1.0, 0.0))
+                / Complex::new(0.0, b);
+        }
+        // Completing the square turns the heading into
+        // a * (t + b / 2a)^2 - b^2 / 4a, which scaled
+        // to pi/2 u^2 is what the Fresnel integrals take.
+        let scale = (PI / (2.0 * a.abs())).sqrt();
+        let offset = b / (2.0 * a);
+        let (c0, s0) = fresnel(offset / scale);
+        let (c1, s1) = fresnel((s + offset) / scale);
+        let fresnel = Complex::new(c1 - c0, a.signum() * (s1 - s0));
+        let phase = -b * b / (4.0 * a);
+        Complex::new(phase.cos(), phase.sin()) * fresnel * scale
+    }
+}
+
+impl PathSegment for ClothoidSegment
+{
+    fn length(&self) -> f64
+    {
+        self.length
+    }
+
+    fn at(&self, position: f64) -> (Vector, Rotation)
+    {
+        let s = position * self.length;
+        let pos = self.integrate(s);
+        (Vector::new(pos.re, pos.im), Rotation::new(self.heading(position)))
+    }
+
+    fn tangent(&self, position: f64) -> Vector
+    {
+        let heading = self.heading(position);
+        Vector::new(heading.cos(), heading.sin())
+    }
+
+    fn heading(&self, position: f64) -> f64
+    {
+        let s = position * self.length;
+        self.sharpness() * s * s + self.start_curvature * s
+    }
+
+    fn curvature(&self, position: f64) -> f64
+    {
+        self.start_curvature + (self.end_curvature - self.start_curvature) * position
+    }
+
+    fn spec(&self) -> Option<SegmentSpec>
+    {
+        Some(SegmentSpec::Clothoid{
+            length: self.length,
+            start_curvature: self.start_curvature,
+            end_curvature: self.end_curvature,
+        })
+    }
+}
+
+// The constant curvature of lines and circles,
+// None for all other segments.
+fn curvature(segment: &SegmentSpec) -> Option<f64>
+{
+    match segment {
+        SegmentSpec::Linear{..} => Some(0.0),
+        SegmentSpec::Circle{radius, arc} => Some(arc.signum() / radius),
+        _ => None,
+    }
+}
+
+fn segment_length(segment: &SegmentSpec) -> f64
+{
+    match segment {
+        SegmentSpec::Linear{length} => *length,
+        SegmentSpec::Circle{radius, arc} => arc.abs() * radius,
+        _ => 0.0,
+    }
+}
+
+fn shortened(segment: &SegmentSpec, by: f64) -> SegmentSpec
+{
+    match segment {
+        SegmentSpec::Linear{length} => SegmentSpec::Linear{length: length - by},
+        SegmentSpec::Circle{radius, arc} => SegmentSpec::Circle{radius: *radius, arc: arc - arc.signum() * by / radius},
+        segment => segment.clone(),
+    }
+}
+
+// Puts a clothoid of the given length between all
+// neighbouring lines and circles of different
+// curvature. Each transition takes half its length
+// from the segments on both sides, so the heading at
+// the end of the path stays the same. Positions move
+// slightly towards the inside of the curves.
+// Segments too short to give up their share keep
+// their hard transition.
+pub fn insert_transitions(segments: &[SegmentSpec], length: f64) -> Vec<SegmentSpec>
+{
+    let half = length / 2.0;
+    let mut segments = segments.to_vec();
+    let mut transitions = vec![None; segments.len()];
+    for index in 1..segments.len() {
+        let (before, after) = (&segments[index - 1], &segments[index]);
+        if let (Some(start_curvature), Some(end_curvature)) = (curvature(before), curvature(after)) {
+            if start_curvature != end_curvature && segment_length(before) > half && segment_length(after) > half {
+                transitions[index] = Some(SegmentSpec::Clothoid{length, start_curvature, end_curvature});
+                segments[index - 1] = shortened(&segments[index - 1], half);
+                segments[index] = shortened(&segments[index], half);
+            }
+        }
+    }
+    let mut result = Vec::new();
+    for (segment, transition) in segments.into_iter().zip(transitions) {
+        if let Some(transition) = transition {
+            result.push(transition);
+        }
+        result.push(segment);
+    }
+    result
+}
+
+// Rebuilds the path with clothoid transitions
+// between its top level segments. None if the path
+// holds segments without a spec.
+pub fn with_transitions(path: &CompoundPath, length: f64) -> Option<CompoundPath>
+{
+    let segments = match path.spec()? {
+        SegmentSpec::Compound{segments} => segments,
+        segment => vec![segment],
+    };
+    let mut result = CompoundPath::new();
+    for segment in insert_transitions(&segments, length) {
+        result.push(segment.build());
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::path::{CircleSegment, LinearSegment};
+
+    fn equal_eps(a: &Vector, b: &Vector, e: f64) -> bool
+    {
+        (b - a).norm() <= e
+    }
+
+    // Simpson's rule over the heading, to check
+    // the closed form against.
+    fn integrate_numerically(segment: &ClothoidSegment, position: f64) -> Vector
+    {
+        let steps = 10000;
+        let h = segment.length * position / steps as f64;
+        let direction = |s: f64| {
+            let heading = segment.sharpness() * s * s + segment.start_curvature * s;
+            Vector::new(heading.cos(), heading.sin())
+        };
+        let mut sum = direction(0.0) + direction(h * steps as f64);
+        for step in 1..steps {
+            let factor = if step % 2 == 1 { 4.0 } else { 2.0 };
+            sum += direction(h * step as f64) * factor;
+        }
+        sum * h / 3.0
+    }
+
+    #[test]
+    fn fresnel_values() {
+        let cases = [
+            (0.5, 0.4923442258714464, 0.0647324328599064),
+            (1.0, 0.7798934003768228, 0.4382591473903548),
+            (2.0, 0.4882534060753408, 0.3434156783636982),
+        ];
+        for (x, c, s) in cases.iter() {
+            let (fc, fs) = fresnel(*x);
+            assert!((fc - c).abs() < 1e-12, "C({})", x);
+            assert!((fs - s).abs() < 1e-12, "S({})", x);
+            assert_eq!(fresnel(-x), (-fc, -fs));
+        }
+        assert_eq!(fresnel(0.0), (0.0, 0.0));
+        // both approach 1/2
+        let (c, s) = fresnel(1000.0);
+        assert!((c - 0.5).abs() < 0.001 && (s - 0.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn fresnel_around_the_series_limit() {
+        // on both sides of where the series
+        // hands over to the asymptotic expansion
+        let cases = [
+            (3.0, 0.6057207892976856, 0.496312998967375),
+            (3.4, 0.4384917033638029, 0.4296494644439269),
+            (4.0, 0.4984260330381776, 0.4205157542469284),
+            (10.0, 0.4998986942055157, 0.4681699785848822),
+        ];
+        for (x, c, s) in cases.iter() {
+            let (fc, fs) = fresnel(*x);
+            assert!((fc - c).abs() < 1e-8, "C({})", x);
+            assert!((fs - s).abs() < 1e-8, "S({})", x);
+        }
+        let below = fresnel(FRESNEL_SERIES_LIMIT);
+        let above = fresnel(FRESNEL_SERIES_LIMIT + 1e-12);
+        assert!((below.0 - above.0).abs() < 1e-8 && (below.1 - above.1).abs() < 1e-8);
+        assert!(fresnel(f64::NAN).0.is_nan());
+    }
+
+    #[test]
+    fn clothoid_matches_numeric_integration() {
+        for segment in [
+            ClothoidSegment::new(20.0, 0.0, 0.25),
+            ClothoidSegment::new(20.0, 0.1, -0.05),
+            ClothoidSegment::new(50.0, -0.2, 0.0),
+        ].iter() {
+            for position in [0.25, 0.5, 1.0].iter() {
+                let (pos, _) = segment.at(*position);
+                assert!(equal_eps(&pos, &integrate_numerically(segment, *position), 1e-6));
+            }
+        }
+    }
+
+    #[test]
+    fn clothoid_heading_and_curvature() {
+        // Going from straight to radius 4 turns
+        // half as far as the circle would.
+        let segment = ClothoidSegment::new(2.0, 0.0, 0.25);
+        let (pos, rot) = segment.at(0.0);
+        assert_eq!(pos, Vector::new(0.0, 0.0));
+        assert_eq!(rot.angle(), 0.0);
+        let (_, rot) = segment.at(1.0);
+        assert!((rot.angle() - 0.25).abs() < 1e-12);
+        assert_eq!(segment.curvature(0.5), 0.125);
+        assert_eq!(segment.curvature(1.0), 0.25);
+    }
+
+    #[test]
+    fn constant_curvature_is_a_circle() {
+        let clothoid = ClothoidSegment::new(2.0 * PI, 0.25, 0.25);
+        let circle = CircleSegment::new(4.0, PI / 2.0);
+        assert!(equal_eps(&clothoid.at(1.0).0, &circle.at(1.0).0, 1e-9));
+        let straight = ClothoidSegment::new(10.0, 0.0, 0.0);
+        assert!(equal_eps(&straight.at(1.0).0, &LinearSegment::new(10.0).at(1.0).0, 1e-12));
+    }
+
+    #[test]
+    fn transitions_keep_the_heading() {
+        let mut path = CompoundPath::new();
+        path.push(Box::new(LinearSegment::new(10.0)));
+        path.push(Box::new(CircleSegment::new(4.0, PI / 2.0)));
+        path.push(Box::new(LinearSegment::new(2.0)));
+        let smoothed = with_transitions(&path, 2.0).unwrap();
+        assert_eq!(smoothed.boundaries().len(), 5);
+        let (pos, rot) = smoothed.at(1.0);
+        assert!((rot.angle() - PI / 2.0).abs() < 1e-9);
+        assert!(equal_eps(&pos, &Vector::new(14.0, 6.0), 0.2));
+        assert!((smoothed.length() - path.length()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn short_segments_keep_hard_transitions() {
+        let segments = vec![
+            SegmentSpec::Linear{length: 0.5},
+            SegmentSpec::Circle{radius: 4.0, arc: PI / 2.0},
+        ];
+        assert_eq!(insert_transitions(&segments, 2.0), segments);
+    }
+}
@@ -0,0 +1,474 @@
+// Driving a path with pure pursuit.
+//
+// Each tick the pose from the odometry is projected
+// onto the path, and the robot steers on the arc
+// through a point the lookahead distance further
+// down the path. The path is followed one run at a
+// time, so the lookahead never reaches past a change
+// of direction, and the robot slows down to stop
+// at the end of each run. Where the path turns on
+// the spot the robot does so too, until it faces
+// the way the path continues.
+use crate::md23::DriveCommand;
+use crate::motor::MotorController;
+use crate::path::{CompoundPath, Direction, PathSegment, Pose, Rotation, PROJECTION_WINDOW};
+use crate::twowheel::TwoWheelRobot;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FollowerSettings
+{
+    // How far ahead of the robot it steers to, in cm
+    pub lookahead: f64,
+    // Cruising speed in cm/s
+    pub speed: f64,
+    // Used to slow down towards the end of
+    // a run, in cm/s^2
+    pub deceleration: f64,
+    // Never slower than this, so the robot
+    // actually gets to the end, in cm/s
+    pub min_speed: f64,
+    // How close to the end of a run counts
+    // as having arrived, in cm
+    pub tolerance: f64,
+    // How close to the heading at the end of
+    // a spin counts as having arrived, in radians
+    pub heading_tolerance: f64,
+    // The wheel speed at full power, in cm/s
+    pub max_speed: f64,
+}
+
+impl Default for FollowerSettings {
+    fn default() -> Self {
+        FollowerSettings{
+            lookahead: 20.0,
+            speed: 20.0,
+            deceleration: 20.0,
+            min_speed: 2.0,
+            tolerance: 1.0,
+            heading_tolerance: 0.02,
+            max_speed: 50.0,
+        }
+    }
+}
+
+// The slowest full power speed a follower
+// accepts, in cm/s
+pub const FOLLOWER_MIN_MAX_SPEED: f64 = 1.0;
+
+// What to do for one tick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step
+{
+    // Wheel speeds in cm/s
+    pub left: f64,
+    pub right: f64,
+    // The relative position along the path
+    pub progress: f64,
+    // How far the robot is off the path,
+    // positive when left of it
+    pub cross_track: f64,
+    pub finished: bool,
+}
+
+impl Step {
+
+    // The wheel speeds as an MD23 command, with
+    // max_speed in cm/s being full power. Faster
+    // speeds are scaled down together, so the
+    // robot keeps turning the same way. When
+    // max_speed isn't positive, the faster
+    // wheel gets full power.
+    pub fn command(&self, max_speed: f64) -> DriveCommand
+    {
+        let full_power = self.left.abs().max(self.right.abs()).max(max_speed);
+        if full_power.is_nan() || full_power <= 0.0 {
+            return DriveCommand::SignedWheels{left: 0.0, right: 0.0};
+        }
+        DriveCommand::SignedWheels{left: (self.left / full_power) as f32, right: (self.right / full_power) as f32}
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Motion
+{
+    Drive(Direction),
+    // Turning on the spot by the angle,
+    // which can be more than a full turn
+    Spin(f64),
+}
+
+// A stretch of the path driven one way
+#[derive(Clone, Copy, Debug)]
+struct Leg
+{
+    // relative positions along the path
+    start: f64,
+    end: f64,
+    motion: Motion,
+}
+
+// Splits the path where the direction changes,
+// and around where it turns on the spot.
+fn legs(path: &CompoundPath) -> Vec<Leg>
+{
+    let runs = path.runs();
+    let spins = path.spins();
+    let mut cuts: Vec<f64> = runs.iter().map(|(start, _)| *start)
+        .chain(spins.iter().flat_map(|(start, end)| vec![*start, *end]))
+        .chain(vec![0.0, 1.0])
+        .collect();
+    cuts.sort_by(|a, b| a.total_cmp(b));
+    cuts.dedup();
+    cuts.windows(2).filter(|cut| cut[0] < cut[1]).map(|cut| {
+        let (start, end) = (cut[0], cut[1]);
+        let middle = (start + end) / 2.0;
+        let motion = if spins.iter().any(|(spin_start, spin_end)| *spin_start <= middle && middle <= *spin_end) {
+            Motion::Spin(path.heading(end) - path.heading(start))
+        }
+        else
+        {
+            let (_, direction) = runs.iter().rev()
+                .find(|(run_start, _)| *run_start <= middle)
+                .copied()
+                .unwrap_or((0.0, Direction::Forward));
+            Motion::Drive(direction)
+        };
+        Leg{start, end, motion}
+    }).collect()
+}
+
+pub struct PathFollower<'a>
+{
+    path: &'a CompoundPath,
+    robot: TwoWheelRobot,
+    settings: FollowerSettings,
+    legs: Vec<Leg>,
+    // the leg currently driven
+    leg: usize,
+    progress: f64,
+    // How far the robot turned on the current
+    // spin, and the heading it last reported
+    turned: f64,
+    last_rotation: Option<Rotation>,
+    finished: bool,
+}
+
+impl<'a> PathFollower<'a> {
+
+    // A max_speed below FOLLOWER_MIN_MAX_SPEED,
+    // or none at all, is raised to it.
+    pub fn new(path: &'a CompoundPath, robot: TwoWheelRobot, settings: FollowerSettings) -> PathFollower<'a>
+    {
+        let settings = FollowerSettings{max_speed: settings.max_speed.max(FOLLOWER_MIN_MAX_SPEED), ..settings};
+        PathFollower{
+            path,
+            robot,
+            settings,
+            legs: legs(path),
+            leg: 0,
+            progress: 0.0,
+            turned: 0.0,
+            last_rotation: None,
+            finished: path.is_empty(),
+        }
+    }
+
+    pub fn settings(&self) -> FollowerSettings
+    {
+        self.settings
+    }
+
+    // The relative position along the path
+    // reached so far.
+    pub fn progress(&self) -> f64
+    {
+        self.progress
+    }
+
+    pub fn is_finished(&self) -> bool
+    {
+        self.finished
+    }
+
+    // The wheel speeds for the robot at the given pose.
+    // Once the end of the path is reached, the robot
+    // is told to stand still.
+    pub fn update(&mut self, pose: &Pose) -> Step
+    {
+        let extent = self.path.extent();
+        let mut cross_track = 0.0;
+        while !self.finished {
+            let leg = self.legs[self.leg];
+            // around where the robot was last time,
+            // so it keeps to the right part of the
+            // path where it crosses itself
+            let window = if extent > 0.0 { PROJECTION_WINDOW / extent } else { 1.0 };
+            let from = leg.start.max(self.progress - window);
+            let to = leg.end.min(self.progress + window);
+            let projection = match self.path.project_pose_within(pose, from, to) {
+                Some(projection) => projection,
+                None => break,
+            };
+            cross_track = projection.cross_track;
+            let arrived = match leg.motion {
+                Motion::Drive(_) => {
+                    self.progress = self.progress.max(projection.position);
+                    (leg.end - self.progress) * extent <= self.settings.tolerance
+                },
+                Motion::Spin(angle) => self.spin_arrived(pose, &leg, angle),
+            };
+            if !arrived {
+                break;
+            }
+            self.turned = 0.0;
+            self.last_rotation = None;
+            if self.leg + 1 < self.legs.len() {
+                self.leg += 1;
+                self.progress = self.legs[self.leg].start;
+            }
+            else
+            {
+                self.progress = 1.0;
+                self.finished = true;
+            }
+        }
+        if self.finished {
+            return Step{left: 0.0, right: 0.0, progress: 1.0, cross_track, finished: true};
+        }
+        let leg = self.legs[self.leg];
+        let (left, right) = match leg.motion {
+            Motion::Drive(direction) => self.steer(pose, &leg, direction),
+            Motion::Spin(angle) => self.spin(angle),
+        };
+        Step{left, right, progress: self.progress, cross_track, finished: false}
+    }
+
+    // Steps the controller, and sends the result
+    // to the motors, stopping them at the end.
+    pub fn drive(&mut self, pose: &Pose, controller: &mut dyn MotorController) -> Step
+    {
+        let step = self.update(pose);
+        if step.finished {
+            controller.stop();
+        }
+        else if let DriveCommand::SignedWheels{left, right} = step.command(self.settings.max_speed)
+        {
+            controller.set_wheel_speeds(left, right);
+        }
+        step
+    }
+
+    // The turn is counted from the headings the
+    // robot reports, so spins of a full turn or
+    // more are turned all the way.
+    fn spin_arrived(&mut self, pose: &Pose, leg: &Leg, angle: f64) -> bool
+    {
+        if let Some(last) = self.last_rotation.replace(pose.rotation) {
+            self.turned += (pose.rotation * last.inverse()).angle();
+        }
+        let share = (self.turned / angle).clamp(0.0, 1.0);
+        self.progress = self.progress.max(leg.start + (leg.end - leg.start) * share);
+        (angle - self.turned).abs() <= self.settings.heading_tolerance
+    }
+
+    // The speed to drive at with the given
+    // distance left to go, in cm
+    fn speed(&self, remaining: f64) -> f64
+    {
+        (2.0 * self.settings.deceleration * remaining).sqrt()
+            .min(self.settings.speed)
+            .max(self.settings.min_speed)
+    }
+
+    fn spin(&self, angle: f64) -> (f64, f64)
+    {
+        let remaining = angle - self.turned;
+        let speed = self.speed(remaining.abs() * self.robot.wheelbase() / 2.0);
+        let turn = if remaining < 0.0 { -speed } else { speed };
+        (-turn, turn)
+    }
+
+    fn steer(&self, pose: &Pose, leg: &Leg, direction: Direction) -> (f64, f64)
+    {
+        let extent = self.path.extent();
+        let speed = self.speed((leg.end - self.progress) * extent);
+        let target = (self.progress + self.settings.lookahead / extent).min(leg.end);
+        let (target, _) = self.path.at(target);
+        // the target as seen in the direction
+        // the robot moves
+        let mut ahead = pose.rotation.inverse_transform_vector(&(target - pose.position));
+        if direction == Direction::Reverse {
+            ahead = -ahead;
+        }
+        let half_wheelbase = self.robot.wheelbase() / 2.0;
+        // behind the robot, it turns on the spot
+        // until it faces the target
+        if ahead[0] < 0.0 {
+            let turn = if ahead[1] < 0.0 { -speed } else { speed };
+            return (-turn, turn);
+        }
+        let distance = ahead.norm_squared();
+        let curvature = if distance > 0.0 { 2.0 * ahead[1] / distance } else { 0.0 };
+        let turn = speed * curvature * half_wheelbase;
+        let speed = speed * direction.sign();
+        (speed - turn, speed + turn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use crate::path::{CircleSegment, LinearSegment, ReverseSegment, RotateSegment, Vector};
+
+    const TICK: f64 = 0.02;
+
+    fn robot() -> TwoWheelRobot
+    {
+        TwoWheelRobot::new(20.0, 10.0)
+    }
+
+    // Drives the robot with the commanded wheel
+    // speeds until the follower is done, and
+    // returns the final pose and all steps.
+    fn simulate(path: &CompoundPath, pose: Pose) -> (Pose, Vec<Step>)
+    {
+        let mut follower = PathFollower::new(path, robot(), FollowerSettings::default());
+        let mut pose = pose;
+        let mut steps = Vec::new();
+        for _ in 0..10000 {
+            let step = follower.update(&pose);
+            steps.push(step);
+            if step.finished {
+                break;
+            }
+            let distance = (step.left + step.right) / 2.0 * TICK;
+            let turn = (step.right - step.left) / robot().wheelbase() * TICK;
+            let heading = pose.heading() + turn / 2.0;
+            pose = Pose{
+                position: pose.position + Rotation::new(heading).transform_vector(&Vector::new(distance, 0.0)),
+                rotation: Rotation::new(pose.heading() + turn),
+            };
+        }
+        (pose, steps)
+    }
+
+    #[test]
+    fn converges_onto_a_line() {
+        let mut path = CompoundPath::new();
+        path.push(Box::new(LinearSegment::new(200.0)));
+        let (pose, steps) = simulate(&path, Pose::new(0.0, 10.0, 0.0));
+        let last = steps.last().unwrap();
+        assert!(last.finished);
+        assert_eq!((last.left, last.right, last.progress), (0.0, 0.0, 1.0));
+        assert!((pose.position - Vector::new(200.0, 0.0)).norm() < 2.0, "{:?}", pose);
+        // the robot starts left of the path
+        assert!(steps[0].cross_track > 9.9);
+        assert!(steps[0].right < steps[0].left);
+        // and progress only ever goes forward
+        assert!(steps.windows(2).all(|pair| pair[0].progress <= pair[1].progress));
+    }
+
+    #[test]
+    fn follows_a_turn() {
+        let mut path = CompoundPath::new();
+        path
+            .push(Box::new(LinearSegment::new(50.0)))
+            .push(Box::new(CircleSegment::new(50.0, PI / 2.0)))
+            .push(Box::new(LinearSegment::new(50.0)));
+        let (pose, steps) = simulate(&path, Pose::origin());
+        assert!(steps.last().unwrap().finished);
+        assert!(steps.iter().all(|step| step.cross_track.abs() < 5.0));
+        assert!((pose.position - Vector::new(100.0, 100.0)).norm() < 2.0, "{:?}", pose);
+    }
+
+    #[test]
+    fn stops_and_reverses() {
+        let mut path = CompoundPath::new();
+        path
+            .push(Box::new(LinearSegment::new(60.0)))
+            .push(Box::new(ReverseSegment::new(Box::new(LinearSegment::new(60.0)))));
+        let (pose, steps) = simulate(&path, Pose::origin());
+        assert!(steps.last().unwrap().finished);
+        assert!(steps.iter().any(|step| step.left < 0.0 && step.right < 0.0));
+        assert!(pose.position.norm() < 2.0, "{:?}", pose);
+        // still facing the way it started
+        assert!(pose.heading().abs() < 0.01);
+    }
+
+    #[test]
+    fn ends_with_a_spin() {
+        let mut path = CompoundPath::new();
+        path
+            .push(Box::new(LinearSegment::new(50.0)))
+            .push(Box::new(RotateSegment::new(-PI / 2.0, 10.0)));
+        let (pose, steps) = simulate(&path, Pose::origin());
+        let last = steps.last().unwrap();
+        assert!(last.finished);
+        assert_eq!(last.progress, 1.0);
+        assert!((pose.position - Vector::new(50.0, 0.0)).norm() < 2.0, "{:?}", pose);
+        assert!((pose.heading() + PI / 2.0).abs() < 0.03, "{:?}", pose);
+    }
+
+    #[test]
+    fn spins_in_the_middle() {
+        // a full turn ends facing the same way,
+        // but still has to be turned
+        let mut path = CompoundPath::new();
+        path
+            .push(Box::new(LinearSegment::new(50.0)))
+            .push(Box::new(RotateSegment::new(2.0 * PI, 10.0)))
+            .push(Box::new(RotateSegment::new(PI / 2.0, 10.0)))
+            .push(Box::new(LinearSegment::new(50.0)));
+        let (pose, steps) = simulate(&path, Pose::origin());
+        assert!(steps.last().unwrap().finished);
+        assert!((pose.position - Vector::new(50.0, 50.0)).norm() < 2.0, "{:?}", pose);
+        assert!((pose.heading() - PI / 2.0).abs() < 0.05, "{:?}", pose);
+        // and turned left on the spot for
+        // the one and a quarter turns
+        let turned: f64 = steps.iter()
+            .filter(|step| step.left < 0.0 && step.right > 0.0)
+            .map(|step| (step.right - step.left) / robot().wheelbase() * TICK)
+            .sum();
+        assert!((turned - 2.5 * PI).abs() < 0.1, "{}", turned);
+        assert!(steps.windows(2).all(|pair| pair[0].progress <= pair[1].progress));
+    }
+
+    #[test]
+    fn finished_stays_finished() {
+        let mut path = CompoundPath::new();
+        path.push(Box::new(LinearSegment::new(50.0)));
+        let mut follower = PathFollower::new(&path, robot(), FollowerSettings::default());
+        assert!(!follower.update(&Pose::origin()).finished);
+        assert!(follower.update(&Pose::new(50.5, 0.0, 0.0)).finished);
+        let step = follower.update(&Pose::origin());
+        assert!(step.finished && follower.is_finished());
+        assert_eq!(step.command(30.0), DriveCommand::SignedWheels{left: 0.0, right: 0.0});
+
+        let empty = CompoundPath::new();
+        assert!(PathFollower::new(&empty, robot(), FollowerSettings::default()).update(&Pose::origin()).finished);
+    }
+
+    #[test]
+    fn commands_keep_the_turn() {
+        let step = Step{left: 15.0, right: 30.0, progress: 0.5, cross_track: 0.0, finished: false};
+        assert_eq!(step.command(60.0), DriveCommand::SignedWheels{left: 0.25, right: 0.5});
+        assert_eq!(step.command(15.0), DriveCommand::SignedWheels{left: 0.5, right: 1.0});
+        for max_speed in &[0.0, -10.0, f64::NAN] {
+            assert_eq!(step.command(*max_speed), DriveCommand::SignedWheels{left: 0.5, right: 1.0});
+        }
+        let standing = Step{left: 0.0, right: 0.0, ..step};
+        assert_eq!(standing.command(0.0), DriveCommand::SignedWheels{left: 0.0, right: 0.0});
+    }
+
+    #[test]
+    fn max_speed_is_raised() {
+        let mut path = CompoundPath::new();
+        path.push(Box::new(LinearSegment::new(50.0)));
+        for max_speed in &[0.0, -10.0, f64::NAN] {
+            let settings = FollowerSettings{max_speed: *max_speed, ..FollowerSettings::default()};
+            let follower = PathFollower::new(&path, robot(), settings);
+            assert_eq!(follower.settings().max_speed, FOLLOWER_MIN_MAX_SPEED);
+        }
+    }
+}
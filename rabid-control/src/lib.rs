@@ -1,2 +1,15 @@
 pub mod path;
+pub mod clothoid;
+pub mod bezier;
+pub mod pathfile;
+pub mod pathscript;
+pub mod render;
 pub mod md23;
+pub mod md23sim;
+pub mod twowheel;
+pub mod trajectory;
+pub mod follower;
+pub mod odometry;
+pub mod velocity;
+pub mod motor;
+pub mod battery;
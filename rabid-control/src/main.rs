@@ -5,11 +5,9 @@ use serde::{Serialize, Deserialize};
 use nanomsg::{Socket, Protocol, Error};
 use std::io::{Read};
 
-mod md23;
-use md23::{MD23Driver, State};
-
-mod path;
-mod twowheel;
+use rr::battery::Level;
+use rr::md23::{MD23Driver, MD23Settings, MD23_DEFAULT_ACCELERATION};
+use rr::motor::{MotorController, Telemetry, Fault, mix};
 
 #[derive(Serialize, Deserialize, Debug)]
 struct AxisMovement {
@@ -37,13 +35,20 @@ fn open_socket(addr: &str) -> Result<Receiver<AxisMovement>, Error> {
 }
 
 
-fn output_state(states: &Vec<State>)
+fn output_telemetry(telemetry: &[Telemetry])
 {
-    for state in states.iter() {
-        match state {
-            State::Normal{voltage, enc1, enc2, when, speed1, speed2, ..} => println!("when: {:?}: voltage: {}, enc1: {}, enc2: {} speed1: {} speed2: {}", when, voltage, enc1, enc2, speed1, speed2),
-            State::Error => panic!("Error in I2C communication"),
-            State::LowVoltage => panic!("Robot running low on battery"),
+    for entry in telemetry.iter() {
+        match entry {
+            Telemetry::Wheels(sample) => println!("when: {:?}: voltage: {}, left: {} right: {} left speed: {} right speed: {} left current: {:?} right current: {:?}", sample.when, sample.voltage, sample.left_steps, sample.right_steps, sample.left_speed, sample.right_speed, sample.left_current, sample.right_current),
+            Telemetry::Fault(Fault::Communication(error)) => println!("Motor error: {}", error),
+            Telemetry::Battery(status) => {
+                println!("battery: {:.1}V {:.0}% remaining: {:?}", status.voltage, status.state_of_charge, status.remaining);
+                if status.level == Level::Warning {
+                    println!("Robot running low on battery");
+                }
+            },
+            Telemetry::Fault(Fault::LowVoltage) => println!("Battery empty, motors stopped until it recovers"),
+            Telemetry::Fault(Fault::CommandTimeout) => println!("No fresh drive command from the remote, stopping"),
             _ => {}
         }
     }
@@ -61,7 +66,16 @@ fn ctrl_channel() -> Result<Receiver<()>, ctrlc::Error> {
 fn main()
 {
     let ctrl_c_events = ctrl_channel().expect("SIGINT handler error");
-    let mut md23 = MD23Driver::new(3);
+    let mut settings = MD23Settings::new(3, MD23_DEFAULT_ACCELERATION);
+    // The remote repeats its axis values every 100ms,
+    // so this allows for a few lost messages.
+    settings.command_timeout = Some(Duration::from_millis(500));
+    let mut md23 = MD23Driver::with_settings(settings);
+    run(&mut md23, ctrl_c_events);
+}
+
+fn run<M: MotorController>(motors: &mut M, ctrl_c_events: Receiver<()>)
+{
     let ticks = tick(Duration::from_millis(100));
     let axis_receiver = open_socket("tcp://0.0.0.0:5000").expect("Socket error");
     let dead_zone = 10_000;
@@ -70,12 +84,13 @@ fn main()
     loop {
         select! {
             recv(ticks) -> _ => {
-                let res = md23.state();
-                output_state(&res);
+                output_telemetry(&motors.telemetry());
             }
             recv(ctrl_c_events) -> _ => {
                 println!("Got SIGINT - goodbye!");
-                md23.shutdown();
+                if let Err(error) = motors.shutdown(Duration::from_secs(1)) {
+                    println!("Motors might still be running: {:?}", error);
+                }
                 break;
             },
             recv(axis_receiver) -> message =>
@@ -95,7 +110,8 @@ fn main()
                         turn = 0.0;
                     }
                 }
-                md23.drive(speed, turn);
+                let (left, right) = mix(speed, turn);
+                motors.set_wheel_speeds(left, right);
                 println!("axis {} moves {}", axis, value);
             }
         }
@@ -1,30 +1,377 @@
 // A driver for the MD23 robot kit
 //
+use std::fmt;
 use std::thread;
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
 use byteorder::{ByteOrder, BigEndian};
 
 use i2cdev::core::I2CDevice;
+
+use crate::motor::{MotorController, Telemetry, WheelSample, Fault, ShutdownError};
+use crate::battery::{Battery, BatterySettings, BatteryStatus, Chemistry, Level};
 use i2cdev::linux::{LinuxI2CDevice, LinuxI2CError};
 
 
 const MD23_ADDR: u16 = 0x58;
-const MD23_SPEED1: u8 = 0;
-const MD23_SPEED2: u8 = 1;
-const MD23_MODE: u8 = 15;
-const MD23_ENC1: u8 = 2;
-const MD23_ENC2: u8 = 6;
-const MD23_VOLTAGE: u8 = 10;
-const MD23_ENCODER_STEPS_PER_REVOLUTION: f32 = 360.0;
+pub(crate) const MD23_SPEED1: u8 = 0;
+pub(crate) const MD23_SPEED2: u8 = 1;
+pub(crate) const MD23_MODE: u8 = 15;
+pub(crate) const MD23_ENC1: u8 = 2;
+pub(crate) const MD23_ENC2: u8 = 6;
+pub(crate) const MD23_VOLTAGE: u8 = 10;
+pub(crate) const MD23_CURRENT1: u8 = 11;
+pub(crate) const MD23_CURRENT2: u8 = 12;
+pub(crate) const MD23_REVISION: u8 = 13;
+pub(crate) const MD23_ACCELERATION: u8 = 14;
+pub(crate) const MD23_COMMAND: u8 = 16;
+pub(crate) const MD23_CMD_RESET_ENCODERS: u8 = 0x20;
+pub(crate) const MD23_CMD_DISABLE_SPEED_REGULATION: u8 = 0x30;
+pub(crate) const MD23_CMD_ENABLE_SPEED_REGULATION: u8 = 0x31;
+pub(crate) const MD23_CMD_DISABLE_TIMEOUT: u8 = 0x32;
+pub(crate) const MD23_CMD_ENABLE_TIMEOUT: u8 = 0x33;
+pub(crate) const MD23_ENCODER_STEPS_PER_REVOLUTION: f32 = 360.0;
+// The MD23 accepts acceleration rates from 1 (slowest)
+// to 10 (fastest), 5 is the power-on default.
+pub const MD23_MIN_ACCELERATION: u8 = 1;
+pub const MD23_MAX_ACCELERATION: u8 = 10;
+pub const MD23_DEFAULT_ACCELERATION: u8 = 5;
+const MD23_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MD23_DROP_TIMEOUT: Duration = Duration::from_secs(1);
+// Anything faster than this is a misread encoder.
+const MD23_MAX_PLAUSIBLE_REVOLUTIONS_PER_SECOND: f32 = 20.0;
+
+// The errno values the Linux I2C adapters report
+// for missing acknowledges and timeouts.
+const ENXIO: i32 = 6;
+const ETIMEDOUT: i32 = 110;
+const EREMOTEIO: i32 = 121;
+
+// Register level access to the MD23. The driver
+// only ever talks to the board through this, so
+// it can run against the real I2C device as well
+// as the in-memory MD23Simulator.
+pub trait Bus {
+    type Error: std::error::Error;
+
+    fn read_register(&mut self, register: u8) -> Result<u8, Self::Error>;
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), Self::Error>;
+
+    // Fill buffer with consecutive registers starting at
+    // the given one. Implementations should do this in
+    // a single bus transaction, the default falls back
+    // to reading byte by byte.
+    fn read_registers(&mut self, start: u8, buffer: &mut [u8]) -> Result<(), Self::Error>
+    {
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = self.read_register(start + i as u8)?;
+        }
+        Ok(())
+    }
+
+    // Map a bus specific error onto what the driver reports.
+    fn classify(error: Self::Error) -> DriverError;
+}
+
+impl Bus for LinuxI2CDevice {
+    type Error = LinuxI2CError;
+
+    fn read_register(&mut self, register: u8) -> Result<u8, LinuxI2CError>
+    {
+        self.smbus_read_byte_data(register)
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), LinuxI2CError>
+    {
+        self.smbus_write_byte_data(register, value)
+    }
+
+    fn read_registers(&mut self, start: u8, buffer: &mut [u8]) -> Result<(), LinuxI2CError>
+    {
+        let data = self.smbus_read_i2c_block_data(start, buffer.len() as u8)?;
+        if data.len() != buffer.len() {
+            return Err(LinuxI2CError::Io(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "short MD23 block read"
+            )));
+        }
+        buffer.copy_from_slice(&data);
+        Ok(())
+    }
+
+    fn classify(error: LinuxI2CError) -> DriverError
+    {
+        let error: std::io::Error = error.into();
+        match error.raw_os_error() {
+            Some(ENXIO) | Some(EREMOTEIO) => DriverError::Nack,
+            Some(ETIMEDOUT) => DriverError::Timeout,
+            code => DriverError::Bus(code),
+        }
+    }
+}
+
+// The four operating modes of the MD23, as
+// written into the mode register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    // Independent wheel speeds, 0 full reverse, 128 stop, 255 full forward
+    UnsignedWheels = 0,
+    // Independent wheel speeds, -128 full reverse, 0 stop, 127 full forward
+    SignedWheels = 1,
+    // SPEED1 drives both wheels, SPEED2 turns, both unsigned
+    UnsignedSpeedTurn = 2,
+    // SPEED1 drives both wheels, SPEED2 turns, both signed
+    SignedSpeedTurn = 3,
+}
+
+// A drive command in one of the MD23 modes. All values
+// are normalized to -1.0..1.0, with negative values meaning
+// reverse respectively a turn to the left. The left wheel
+// is connected to motor 1, the right one to motor 2.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DriveCommand {
+    UnsignedWheels{left: f32, right: f32},
+    SignedWheels{left: f32, right: f32},
+    UnsignedSpeedTurn{speed: f32, turn: f32},
+    SignedSpeedTurn{speed: f32, turn: f32},
+}
+
+impl DriveCommand {
+
+    // The command that halts the motors in the given mode.
+    pub fn stop(mode: Mode) -> DriveCommand
+    {
+        match mode {
+            Mode::UnsignedWheels => DriveCommand::UnsignedWheels{left: 0.0, right: 0.0},
+            Mode::SignedWheels => DriveCommand::SignedWheels{left: 0.0, right: 0.0},
+            Mode::UnsignedSpeedTurn => DriveCommand::UnsignedSpeedTurn{speed: 0.0, turn: 0.0},
+            Mode::SignedSpeedTurn => DriveCommand::SignedSpeedTurn{speed: 0.0, turn: 0.0},
+        }
+    }
+
+    pub fn mode(&self) -> Mode
+    {
+        match self {
+            DriveCommand::UnsignedWheels{..} => Mode::UnsignedWheels,
+            DriveCommand::SignedWheels{..} => Mode::SignedWheels,
+            DriveCommand::UnsignedSpeedTurn{..} => Mode::UnsignedSpeedTurn,
+            DriveCommand::SignedSpeedTurn{..} => Mode::SignedSpeedTurn,
+        }
+    }
+
+    pub fn is_stop(&self) -> bool
+    {
+        *self == DriveCommand::stop(self.mode())
+    }
+
+    // The same command with all values multiplied by factor.
+    pub fn scaled(&self, factor: f32) -> DriveCommand
+    {
+        match *self {
+            DriveCommand::UnsignedWheels{left, right} => DriveCommand::UnsignedWheels{left: left * factor, right: right * factor},
+            DriveCommand::SignedWheels{left, right} => DriveCommand::SignedWheels{left: left * factor, right: right * factor},
+            DriveCommand::UnsignedSpeedTurn{speed, turn} => DriveCommand::UnsignedSpeedTurn{speed: speed * factor, turn: turn * factor},
+            DriveCommand::SignedSpeedTurn{speed, turn} => DriveCommand::SignedSpeedTurn{speed: speed * factor, turn: turn * factor},
+        }
+    }
+
+    // The values for the SPEED1 and SPEED2 registers.
+    pub fn encode(&self) -> (u8, u8)
+    {
+        match *self {
+            DriveCommand::UnsignedWheels{left, right} => (encode_unsigned(left), encode_unsigned(right)),
+            DriveCommand::SignedWheels{left, right} => (encode_signed(left), encode_signed(right)),
+            DriveCommand::UnsignedSpeedTurn{speed, turn} => (encode_unsigned(speed), encode_unsigned(turn)),
+            DriveCommand::SignedSpeedTurn{speed, turn} => (encode_signed(speed), encode_signed(turn)),
+        }
+    }
+}
+
+fn encode_unsigned(value: f32) -> u8
+{
+    (value.clamp(-1.0, 1.0) * 127.0 + 128.0).round() as u8
+}
+
+fn encode_signed(value: f32) -> u8
+{
+    (value.clamp(-1.0, 1.0) * 127.0).round() as i8 as u8
+}
+
+// What can be written into the command register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlCommand {
+    ResetEncoders,
+    // Automatic speed regulation increases the motor
+    // power when the wheels are held back by load.
+    SpeedRegulation(bool),
+    // Stop the motors when there was no write to
+    // the speed registers for two seconds.
+    MotorTimeout(bool),
+}
+
+impl ControlCommand {
+
+    pub fn encode(&self) -> u8
+    {
+        match self {
+            ControlCommand::ResetEncoders => MD23_CMD_RESET_ENCODERS,
+            ControlCommand::SpeedRegulation(false) => MD23_CMD_DISABLE_SPEED_REGULATION,
+            ControlCommand::SpeedRegulation(true) => MD23_CMD_ENABLE_SPEED_REGULATION,
+            ControlCommand::MotorTimeout(false) => MD23_CMD_DISABLE_TIMEOUT,
+            ControlCommand::MotorTimeout(true) => MD23_CMD_ENABLE_TIMEOUT,
+        }
+    }
+}
 
 enum Message
 {
-    Drive{speed: f32, turn: f32},
+    Drive(DriveCommand),
+    Control(ControlCommand),
+    Stop,
     Shutdown
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DriverError {
+    // The I2C device couldn't be opened at all
+    BusOpen,
+    // The board didn't acknowledge a transfer
+    Nack,
+    Timeout,
+    // The transfer went through, but the values make no sense
+    ImplausibleReading,
+    // Any other bus failure, with the OS error code if known
+    Bus(Option<i32>),
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::BusOpen => write!(f, "can't open the I2C bus"),
+            DriverError::Nack => write!(f, "MD23 didn't acknowledge"),
+            DriverError::Timeout => write!(f, "MD23 transfer timed out"),
+            DriverError::ImplausibleReading => write!(f, "implausible reading from MD23"),
+            DriverError::Bus(Some(code)) => write!(f, "I2C error, OS error code {}", code),
+            DriverError::Bus(None) => write!(f, "I2C error"),
+        }
+    }
+}
+
+impl std::error::Error for DriverError {}
+
+// How the driver thread deals with a failing bus. After
+// each failure it waits, doubling the wait from
+// initial_backoff up to max_backoff, re-initialises
+// the board and tries again. It gives up after the
+// given number of consecutive failures.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl RetryPolicy {
+
+    fn backoff(&self, failures: u32) -> Duration
+    {
+        let factor = 1u32.checked_shl(failures.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.initial_backoff.checked_mul(factor).unwrap_or(self.max_backoff).min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy{
+            attempts: 8,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MD23Settings {
+    pub battery: BatterySettings,
+    pub acceleration: u8,
+    pub retry: RetryPolicy,
+    // If set, a drive command that isn't renewed within
+    // this time is ramped down to a stop over timeout_ramp.
+    pub command_timeout: Option<Duration>,
+    pub timeout_ramp: Duration,
+}
+
+impl MD23Settings {
+
+    // Assumes a LiPo pack
+    pub fn new(battery_cell_count: u8, acceleration: u8) -> MD23Settings
+    {
+        MD23Settings{
+            battery: BatterySettings::new(Chemistry::LiPo, battery_cell_count),
+            acceleration,
+            retry: RetryPolicy::default(),
+            command_timeout: None,
+            timeout_ramp: Duration::from_millis(500),
+        }
+    }
+}
+
+// The deadman switch: keeps track of the last drive
+// command, and ramps it down to a stop if it isn't
+// renewed in time.
+struct Watchdog {
+    timeout: Duration,
+    ramp: Duration,
+    command: DriveCommand,
+    issued: Instant,
+    tripped: bool,
+    // nothing (left) to ramp down
+    idle: bool,
+}
+
+impl Watchdog {
+
+    fn new(timeout: Duration, ramp: Duration) -> Watchdog
+    {
+        Watchdog{
+            timeout,
+            ramp,
+            command: DriveCommand::stop(Mode::UnsignedSpeedTurn),
+            issued: Instant::now(),
+            tripped: false,
+            idle: true,
+        }
+    }
+
+    fn renew(&mut self, command: DriveCommand, now: Instant)
+    {
+        self.command = command;
+        self.issued = now;
+        self.tripped = false;
+        self.idle = command.is_stop();
+    }
+
+    // The ramped down command to write once the timeout
+    // expired, and whether it expired just now.
+    fn check(&mut self, now: Instant) -> Option<(DriveCommand, bool)>
+    {
+        let overdue = now.saturating_duration_since(self.issued).checked_sub(self.timeout)?;
+        if self.idle || overdue == Duration::from_secs(0) {
+            return None;
+        }
+        let factor = if self.ramp > overdue {
+            1.0 - overdue.as_secs_f32() / self.ramp.as_secs_f32()
+        } else {
+            self.idle = true;
+            0.0
+        };
+        let tripped = !self.tripped;
+        self.tripped = true;
+        Some((self.command.scaled(factor), tripped))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub enum State {
     Normal
     {
@@ -36,33 +383,119 @@ pub enum State {
         diff2: i32,
         speed1: f32,    // Given in revolutions/second, sign indicates direction
         speed2: f32,
+        current1: f32,  // Given in Ampere
+        current2: f32,
+        revision: u8,
+        acceleration: u8,
+        battery: BatteryStatus,
     },
-    LowVoltage,
-    Error,
+    // The battery reached its cutoff level, the motors
+    // are stopped until it recovered.
+    LowVoltage(BatteryStatus),
+    // Sent once when the drive command wasn't renewed
+    // in time, and the motors are being stopped.
+    CommandTimeout,
+    Error(DriverError),
     Shutdown,
 }
 
+// One poll worth of measurements. These are fetched
+// with a single block read, so the encoder bytes
+// can't change between reading them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Reading {
+    enc1: u32,
+    enc2: u32,
+    voltage: f32,
+    current1: f32,
+    current2: f32,
+}
+
+impl Reading {
+
+    // From ENC1 up to and including CURRENT2
+    const LENGTH: usize = (MD23_CURRENT2 - MD23_ENC1 + 1) as usize;
+
+    fn read<B: Bus>(dev: &mut B) -> Result<Reading, B::Error>
+    {
+        let mut buffer = [0; Reading::LENGTH];
+        dev.read_registers(MD23_ENC1, &mut buffer)?;
+        Ok(Reading::decode(&buffer))
+    }
+
+    fn decode(buffer: &[u8; Reading::LENGTH]) -> Reading
+    {
+        let offset = |register: u8| (register - MD23_ENC1) as usize;
+        Reading{
+            enc1: BigEndian::read_u32(&buffer[offset(MD23_ENC1)..]),
+            enc2: BigEndian::read_u32(&buffer[offset(MD23_ENC2)..]),
+            voltage: buffer[offset(MD23_VOLTAGE)] as f32 / 10.0,
+            current1: buffer[offset(MD23_CURRENT1)] as f32 / 10.0,
+            current2: buffer[offset(MD23_CURRENT2)] as f32 / 10.0,
+        }
+    }
+}
+
+// What we learn about the board once at startup.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct BoardInfo {
+    revision: u8,
+    acceleration: u8,
+}
+
+impl State {
+
+    // The backend independent view on this state.
+    pub fn telemetry(&self) -> Vec<Telemetry>
+    {
+        match *self {
+            State::Normal{when, voltage, diff1, diff2, speed1, speed2, current1, current2, battery, ..} => vec![
+                Telemetry::Wheels(WheelSample{
+                    when,
+                    voltage,
+                    left_steps: diff1,
+                    right_steps: diff2,
+                    steps_per_revolution: MD23_ENCODER_STEPS_PER_REVOLUTION,
+                    left_speed: speed1,
+                    right_speed: speed2,
+                    left_current: Some(current1),
+                    right_current: Some(current2),
+                }),
+                Telemetry::Battery(battery),
+            ],
+            State::LowVoltage(battery) => vec![Telemetry::Battery(battery), Telemetry::Fault(Fault::LowVoltage)],
+            State::CommandTimeout => vec![Telemetry::Fault(Fault::CommandTimeout)],
+            State::Error(error) => vec![Telemetry::Fault(Fault::Communication(error.to_string()))],
+            State::Shutdown => vec![Telemetry::Shutdown],
+        }
+    }
+}
+
 pub struct MD23Driver {
     outgoing: std::sync::mpsc::Sender<Message>,
     incoming: std::sync::mpsc::Receiver<State>,
+    voltage: Option<f32>,
+    thread: Option<thread::JoinHandle<()>>,
+    // Once the thread is gone, repeated
+    // shutdowns report the same outcome.
+    shutdown_result: Option<Result<(), ShutdownError>>,
 }
 
 impl MD23Driver {
 
-    fn read_encoder(dev: &mut LinuxI2CDevice, address: u8) -> Result<u32, LinuxI2CError>
+    fn setup_board<B: Bus>(dev: &mut B, acceleration: u8) -> Result<BoardInfo, B::Error>
     {
-        let mut vec = Vec::new();
-        for i in 0..4 {
-            vec.push(dev.smbus_read_byte_data(address + i)?);
-        }
-        Ok(BigEndian::read_u32(&vec))
+        dev.write_register(MD23_ACCELERATION, acceleration)?;
+        Ok(BoardInfo{
+            revision: dev.read_register(MD23_REVISION)?,
+            acceleration: dev.read_register(MD23_ACCELERATION)?,
+        })
     }
 
-    fn compute_state(dev: &mut LinuxI2CDevice, battery_cell_count: u8, previous_state: &State) -> Result<State, LinuxI2CError>
+    fn compute_state<B: Bus>(dev: &mut B, battery: &mut Battery, board: &BoardInfo, previous_state: &State) -> Result<State, DriverError>
     {
         let now = Instant::now();
-        let new_enc1 = MD23Driver::read_encoder(dev, MD23_ENC1)?;
-        let new_enc2 = MD23Driver::read_encoder(dev, MD23_ENC2)?;
+        let Reading{enc1: new_enc1, enc2: new_enc2, voltage, current1, current2} = Reading::read(dev).map_err(B::classify)?;
         let mut diff1 = 0;
         let mut diff2 = 0;
         let mut speed1 = 0.0;
@@ -74,106 +507,274 @@ impl MD23Driver {
              diff2 = encoder_diff(&new_enc2, enc2);
              speed1 = diff1 as f32 / (time_delta * MD23_ENCODER_STEPS_PER_REVOLUTION);
              speed2 = diff2 as f32 / (time_delta * MD23_ENCODER_STEPS_PER_REVOLUTION);
+             if speed1.abs().max(speed2.abs()) > MD23_MAX_PLAUSIBLE_REVOLUTIONS_PER_SECOND {
+                 return Err(DriverError::ImplausibleReading);
+             }
         }
 
-        let voltage = dev.smbus_read_byte_data(MD23_VOLTAGE)?;
-        let voltage = voltage as f32 / 10.0;
-        if voltage < 3.3 * battery_cell_count as f32 {
-            return Ok(State::LowVoltage);
+        // The board can't run on no voltage at all,
+        // so this is a botched transfer.
+        if voltage == 0.0 {
+            return Err(DriverError::ImplausibleReading);
+        }
+
+        let status = battery.update(voltage, current1 + current2, now);
+        if status.level == Level::Cutoff {
+            Ok(State::LowVoltage(status))
         } else {
-            return Ok(State::Normal
+            Ok(State::Normal
                       {
                           when: now,
-                          voltage: voltage,
+                          voltage,
                           enc1: new_enc1,
                           enc2: new_enc2,
-                          diff1: diff1,
-                          diff2: diff2,
-                          speed1: speed1,
-                          speed2: speed2,
+                          diff1,
+                          diff2,
+                          speed1,
+                          speed2,
+                          current1,
+                          current2,
+                          revision: board.revision,
+                          acceleration: board.acceleration,
+                          battery: status,
                       }
             )
         }
     }
 
-    fn start_thread(
+    fn write_command<B: Bus>(dev: &mut B, mode: &mut Mode, command: &DriveCommand) -> Result<(), B::Error>
+    {
+        if command.mode() != *mode {
+            dev.write_register(MD23_MODE, command.mode() as u8)?;
+            *mode = command.mode();
+        }
+        let (speed1, speed2) = command.encode();
+        dev.write_register(MD23_SPEED1, speed1)?;
+        dev.write_register(MD23_SPEED2, speed2)?;
+        Ok(())
+    }
+
+    fn write_control<B: Bus>(dev: &mut B, control: &ControlCommand, state: &mut State) -> Result<(), B::Error>
+    {
+        dev.write_register(MD23_COMMAND, control.encode())?;
+        // The next speed computation must start
+        // from the cleared encoders, or we get a
+        // huge jump backwards.
+        if let (ControlCommand::ResetEncoders, State::Normal{enc1, enc2, ..}) = (control, state) {
+            *enc1 = 0;
+            *enc2 = 0;
+        }
+        Ok(())
+    }
+
+    fn stop_motors<B: Bus>(dev: &mut B, mode: &mut Mode) -> Result<(), B::Error>
+    {
+        let command = DriveCommand::stop(*mode);
+        MD23Driver::write_command(dev, mode, &command)
+    }
+
+    // Sleep while backing off from bus errors. Drive
+    // commands arriving meanwhile are dropped, but a
+    // shutdown request ends the wait right away.
+    fn wait_for_shutdown(rx: &mpsc::Receiver<Message>, duration: Duration) -> bool
+    {
+        let deadline = Instant::now() + duration;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(remaining) {
+                Ok(Message::Shutdown) => return true,
+                Ok(_) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => return false,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return true,
+            }
+        }
+    }
+
+    // (Re-)initialise the board: mode, acceleration
+    // rate, and what it tells us about itself.
+    fn setup<B: Bus>(dev: &mut B, mode: Mode, acceleration: u8) -> Result<BoardInfo, DriverError>
+    {
+        dev.write_register(MD23_MODE, mode as u8).map_err(B::classify)?;
+        MD23Driver::setup_board(dev, acceleration).map_err(B::classify)
+    }
+
+    // The bus is opened from within the thread, so
+    // it doesn't have to be Send itself.
+    fn start_thread<B, F>(
+        open: F,
         rx: std::sync::mpsc::Receiver<Message>,
         tx: std::sync::mpsc::Sender<State>,
-        addr: u16,
-        battery_cell_count: u8
-    )
+        settings: MD23Settings
+    ) -> thread::JoinHandle<()>
+    where
+        B: Bus,
+        F: FnOnce() -> Result<B, B::Error> + Send + 'static
     {
         thread::spawn(move || {
-            let mut dev = LinuxI2CDevice::new("/dev/i2c-1", addr).expect("MD23 I2C error");
-            dev.smbus_write_byte_data(MD23_MODE, 2).expect("setting mode failed");
-            let mut state = State::Normal{
-                when: Instant::now(),
-                voltage: -1.0,
-                enc1: 0,
-                enc2: 0,
-                diff1: 0,
-                diff2: 0,
-                speed1: 0.0,
-                speed2: 0.0,
-
+            let mut dev = match open() {
+                Ok(dev) => dev,
+                Err(_) => {
+                    let _ = tx.send(State::Error(DriverError::BusOpen));
+                    return;
+                }
             };
+            let mut mode = Mode::UnsignedSpeedTurn;
+            let mut board = None;
+            let mut failures = 0;
+            let mut watchdog = settings.command_timeout.map(|timeout| Watchdog::new(timeout, settings.timeout_ramp));
+            // There is no previous reading to compute
+            // speeds from, so we start out as if we
+            // had an error.
+            let mut state = State::Error(DriverError::Nack);
+            let mut shutdown = false;
+            let mut battery = Battery::new(settings.battery);
             loop {
-                state = match MD23Driver::compute_state(&mut dev, battery_cell_count, &state)
+                if shutdown {
+                    // Only confirm the shutdown if we know
+                    // the motors got stopped.
+                    let state = match MD23Driver::stop_motors(&mut dev, &mut mode) {
+                        Ok(_) => State::Shutdown,
+                        Err(error) => State::Error(B::classify(error)),
+                    };
+                    let _ = tx.send(state);
+                    break;
+                }
+                let result = match board {
+                    Some(board) => Ok(board),
+                    None => MD23Driver::setup(&mut dev, mode, settings.acceleration),
+                };
+                board = result.as_ref().ok().copied();
+                let previous = state;
+                state = match result.and_then(
+                    |board| MD23Driver::compute_state(&mut dev, &mut battery, &board, &previous))
                 {
                     Ok(state) => state,
-                    Err(_) => State::Error
+                    Err(error) => State::Error(error)
                 };
 
                 match state {
                     State::Normal{..} => {
+                        failures = 0;
                         for message in rx.try_iter()
                         {
-                            match message {
-                                Message::Drive{speed, turn} => {
-                                    let speed = (speed * 127.0 + 128.0) as u8;
-                                    let turn = (turn * 127.0 + 128.0) as u8;
-                                    let mut foo = || -> Result<(), LinuxI2CError>
-                                    {
-                                        dev.smbus_write_byte_data(MD23_SPEED1, speed)?;
-                                        dev.smbus_write_byte_data(MD23_SPEED2, turn)?;
-                                        Ok(())
+                            let result = match message {
+                                Message::Drive(_) | Message::Stop => {
+                                    let command = match message {
+                                        Message::Drive(command) => command,
+                                        _ => DriveCommand::stop(mode),
                                     };
-                                    match foo()
-                                    {
-                                        Ok(_) => {}
-                                        Err(_) => { state = State::Error; }
+                                    if let Some(watchdog) = watchdog.as_mut() {
+                                        watchdog.renew(command, Instant::now());
                                     }
+                                    MD23Driver::write_command(&mut dev, &mut mode, &command)
                                 },
+                                Message::Control(control) => MD23Driver::write_control(&mut dev, &control, &mut state),
                                 Message::Shutdown => {
-                                    state = State::Shutdown;
+                                    shutdown = true;
+                                    Ok(())
                                 }
+                            };
+                            if let Err(error) = result {
+                                state = State::Error(B::classify(error));
+                            }
+                        }
+                        if let (State::Normal{..}, Some(watchdog)) = (state, watchdog.as_mut()) {
+                            if let Some((command, tripped)) = watchdog.check(Instant::now()) {
+                                if tripped && tx.send(State::CommandTimeout).is_err() {
+                                    shutdown = true;
+                                }
+                                if let Err(error) = MD23Driver::write_command(&mut dev, &mut mode, &command) {
+                                    state = State::Error(B::classify(error));
+                                }
+                            }
+                        }
+                    },
+                    // Drive commands are dropped while the
+                    // board isn't usable, but shutting
+                    // down must always be possible.
+                    State::LowVoltage(_) => {
+                        shutdown = rx.try_iter().any(|message| matches!(message, Message::Shutdown));
+                        if !matches!(previous, State::LowVoltage(_)) {
+                            // The watchdog mustn't bring the old
+                            // command back once the battery recovers.
+                            if let Some(watchdog) = watchdog.as_mut() {
+                                watchdog.renew(DriveCommand::stop(mode), Instant::now());
                             }
+                            if let Err(error) = MD23Driver::stop_motors(&mut dev, &mut mode) {
+                                state = State::Error(B::classify(error));
+                            }
+                        }
+                    },
+                    State::Error(_) => {
+                        shutdown = rx.try_iter().any(|message| matches!(message, Message::Shutdown));
+                    },
+                    State::CommandTimeout | State::Shutdown => {}
+                }
+                if shutdown {
+                    continue;
+                }
+                // Nobody listens anymore, so the driver
+                // was dropped without shutting down.
+                if tx.send(state).is_err() {
+                    shutdown = true;
+                    continue;
+                }
+                match state {
+                    State::Error(_) => {
+                        // Back off, and re-initialise the board
+                        // before the next attempt.
+                        failures += 1;
+                        if failures > settings.retry.attempts {
+                            let _ = MD23Driver::stop_motors(&mut dev, &mut mode);
+                            break;
                         }
+                        board = None;
+                        shutdown = MD23Driver::wait_for_shutdown(&rx, settings.retry.backoff(failures));
                     },
-                    State::LowVoltage =>
-                    {
-                    }
-                    State::Error => {
-                    }
-                    State::Shutdown => {
-                        break;
-                    }
+                    _ => thread::sleep(MD23_POLL_INTERVAL),
                 }
-                tx.send(state).expect("thread error");
-                thread::sleep(Duration::from_millis(100));
             }
-        });
+        })
+    }
+
+    // The acceleration rate is clamped to the range
+    // the MD23 accepts, see MD23_MIN_ACCELERATION
+    // and MD23_MAX_ACCELERATION.
+    pub fn new(battery_cell_count: u8, acceleration: u8) -> MD23Driver
+    {
+        MD23Driver::with_settings(MD23Settings::new(battery_cell_count, acceleration))
     }
 
-    pub fn new(battery_cell_count: u8) -> MD23Driver
+    pub fn with_settings(settings: MD23Settings) -> MD23Driver
     {
-        let addr = MD23_ADDR;
+        MD23Driver::spawn(|| LinuxI2CDevice::new("/dev/i2c-1", MD23_ADDR), settings)
+    }
+
+    // Run the driver against an arbitrary bus,
+    // e.g. an MD23Simulator.
+    pub fn with_bus<B: Bus + Send + 'static>(bus: B, settings: MD23Settings) -> MD23Driver
+    {
+        MD23Driver::spawn(move || Ok(bus), settings)
+    }
+
+    fn spawn<B, F>(open: F, settings: MD23Settings) -> MD23Driver
+    where
+        B: Bus,
+        F: FnOnce() -> Result<B, B::Error> + Send + 'static
+    {
+        let settings = MD23Settings{
+            acceleration: settings.acceleration.clamp(MD23_MIN_ACCELERATION, MD23_MAX_ACCELERATION),
+            ..settings
+        };
         let (tx, rx) = mpsc::channel();
         let (tx_incoming, rx_incoming) = mpsc::channel();
-        MD23Driver::start_thread(rx, tx_incoming, addr, battery_cell_count);
+        let thread = MD23Driver::start_thread(open, rx, tx_incoming, settings);
         MD23Driver{
             outgoing: tx,
-            incoming: rx_incoming
+            incoming: rx_incoming,
+            voltage: None,
+            thread: Some(thread),
+            shutdown_result: None,
         }
     }
 
@@ -181,18 +782,63 @@ impl MD23Driver {
     {
         let mut result = Vec::new();
         result.extend(self.incoming.try_iter());
-        return result;
+        for state in result.iter() {
+            if let State::Normal{voltage, ..} = state {
+                self.voltage = Some(*voltage);
+            }
+        }
+        result
     }
 
     pub fn drive(self: &mut MD23Driver, speed: f32, turn: f32) -> Vec<State>
     {
-        self.outgoing.send(Message::Drive{speed, turn}).expect("thread error");
+        self.command(DriveCommand::UnsignedSpeedTurn{speed, turn})
+    }
+
+    // Set the left and right wheel speeds directly,
+    // bypassing the speed/turn mixing.
+    pub fn drive_wheels(self: &mut MD23Driver, left: f32, right: f32) -> Vec<State>
+    {
+        self.command(DriveCommand::SignedWheels{left, right})
+    }
+
+    // Switches the MD23 into the mode of the
+    // command if necessary.
+    pub fn command(self: &mut MD23Driver, command: DriveCommand) -> Vec<State>
+    {
+        // The thread might have given up after too
+        // many bus errors, which it reported already.
+        let _ = self.outgoing.send(Message::Drive(command));
         self.gather_state_messages()
     }
 
+    // Zero both encoders. Telemetry continues
+    // seamlessly, without a jump in diff or speed.
+    pub fn reset_encoders(self: &mut MD23Driver) -> Vec<State>
+    {
+        self.control(ControlCommand::ResetEncoders)
+    }
+
+    pub fn set_speed_regulation(self: &mut MD23Driver, enabled: bool) -> Vec<State>
+    {
+        self.control(ControlCommand::SpeedRegulation(enabled))
+    }
+
+    pub fn set_motor_timeout(self: &mut MD23Driver, enabled: bool) -> Vec<State>
+    {
+        self.control(ControlCommand::MotorTimeout(enabled))
+    }
+
+    pub fn control(self: &mut MD23Driver, control: ControlCommand) -> Vec<State>
+    {
+        let _ = self.outgoing.send(Message::Control(control));
+        self.gather_state_messages()
+    }
+
+    // Halts the motors in whatever mode is active.
     pub fn stop(self: &mut MD23Driver) -> Vec<State>
     {
-        self.outgoing.send(Message::Drive{speed: 0.0, turn: 0.0}).expect("thread error");
+        let _ = self.outgoing.send(Message::Stop);
         self.gather_state_messages()
     }
 
@@ -201,19 +847,73 @@ impl MD23Driver {
         self.gather_state_messages()
     }
 
-    pub fn shutdown(self: &mut MD23Driver)
+    // Stops the motors and ends the driver thread.
+    // Ok means the thread confirmed the motors
+    // stopped within the timeout.
+    pub fn shutdown(self: &mut MD23Driver, timeout: Duration) -> Result<(), ShutdownError>
     {
-        self.stop();
-        self.outgoing.send(Message::Shutdown).expect("thread error");
-        loop {
-            for message in self.incoming.iter() {
-                if let State::Shutdown = message {
-                    return;
-                }
+        if let Some(result) = self.shutdown_result {
+            return result;
+        }
+        let _ = self.outgoing.send(Message::Shutdown);
+        let deadline = Instant::now() + timeout;
+        let result = loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.incoming.recv_timeout(remaining) {
+                Ok(State::Shutdown) => break Ok(()),
+                Ok(_) => {},
+                Err(mpsc::RecvTimeoutError::Timeout) => return Err(ShutdownError::Timeout),
+                Err(mpsc::RecvTimeoutError::Disconnected) => break Err(ShutdownError::NotConfirmed),
             }
+        };
+        // The thread is done, so this doesn't block.
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.shutdown_result = Some(result);
+        result
+    }
+
+}
+
+// Dropping the driver must not leave the motors running.
+impl Drop for MD23Driver {
+    fn drop(&mut self) {
+        if self.shutdown_result.is_none() {
+            let _ = self.shutdown(MD23_DROP_TIMEOUT);
         }
     }
+}
+
+// The MotorController methods don't hand out the
+// states gathered while sending commands, they are
+// all left for telemetry() to pick up.
+impl MotorController for MD23Driver {
+
+    fn set_wheel_speeds(&mut self, left: f32, right: f32)
+    {
+        let _ = self.outgoing.send(Message::Drive(DriveCommand::SignedWheels{left, right}));
+    }
+
+    fn stop(&mut self)
+    {
+        let _ = self.outgoing.send(Message::Stop);
+    }
 
+    fn telemetry(&mut self) -> Vec<Telemetry>
+    {
+        self.gather_state_messages().iter().flat_map(State::telemetry).collect()
+    }
+
+    fn battery_voltage(&self) -> Option<f32>
+    {
+        self.voltage
+    }
+
+    fn shutdown(&mut self, timeout: Duration) -> Result<(), ShutdownError>
+    {
+        MD23Driver::shutdown(self, timeout)
+    }
 }
 
 fn encoder_diff(a: &u32, b: &u32) -> i32
@@ -226,6 +926,590 @@ fn encoder_diff(a: &u32, b: &u32) -> i32
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::md23sim::{MD23Simulator, SimulatorError};
+
+    const BOARD: BoardInfo = BoardInfo{revision: 1, acceleration: MD23_DEFAULT_ACCELERATION};
+
+    fn battery() -> Battery
+    {
+        Battery::new(BatterySettings::new(Chemistry::LiPo, 3))
+    }
+    const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+    // Counts the bus transactions, to make sure
+    // we don't fall back to single register reads.
+    struct CountingBus {
+        sim: MD23Simulator,
+        transactions: usize,
+    }
+
+    impl Bus for CountingBus {
+        type Error = <MD23Simulator as Bus>::Error;
+
+        fn read_register(&mut self, register: u8) -> Result<u8, Self::Error>
+        {
+            self.transactions += 1;
+            self.sim.read_register(register)
+        }
+
+        fn write_register(&mut self, register: u8, value: u8) -> Result<(), Self::Error>
+        {
+            self.transactions += 1;
+            self.sim.write_register(register, value)
+        }
+
+        fn read_registers(&mut self, start: u8, buffer: &mut [u8]) -> Result<(), Self::Error>
+        {
+            self.transactions += 1;
+            self.sim.read_registers(start, buffer)
+        }
+
+        fn classify(error: Self::Error) -> DriverError
+        {
+            MD23Simulator::classify(error)
+        }
+    }
+
+    fn wait_for_states(md23: &mut MD23Driver) -> Vec<State>
+    {
+        thread::sleep(MD23_POLL_INTERVAL * 3);
+        md23.state()
+    }
+
+    #[test]
+    fn compute_state_derives_speed_from_encoders() {
+        let mut sim = MD23Simulator::new();
+        sim.set_encoders(360, -180);
+        let previous = State::Normal{
+            when: Instant::now() - Duration::from_secs(1),
+            voltage: 12.0,
+            enc1: 0,
+            enc2: 0,
+            diff1: 0,
+            diff2: 0,
+            speed1: 0.0,
+            speed2: 0.0,
+            current1: 0.0,
+            current2: 0.0,
+            revision: 0,
+            acceleration: 0,
+            battery: battery().update(12.0, 0.0, Instant::now()),
+        };
+        match MD23Driver::compute_state(&mut sim, &mut battery(), &BOARD, &previous).unwrap() {
+            State::Normal{voltage, enc1, enc2, diff1, diff2, speed1, speed2, ..} => {
+                assert_eq!(voltage, 12.0);
+                assert_eq!(enc1, 360);
+                assert_eq!(enc2, (-180i32) as u32);
+                assert_eq!(diff1, 360);
+                assert_eq!(diff2, -180);
+                assert!((speed1 - 1.0).abs() < 0.05);
+                assert!((speed2 + 0.5).abs() < 0.05);
+            },
+            _ => panic!("expected normal state"),
+        }
+    }
+
+    #[test]
+    fn compute_state_detects_low_voltage() {
+        let mut sim = MD23Simulator::new();
+        sim.set_voltage(9.8);
+        let previous = State::Error(DriverError::Nack);
+        assert!(matches!(
+            MD23Driver::compute_state(&mut sim, &mut battery(), &BOARD, &previous),
+            Ok(State::LowVoltage(BatteryStatus{level: Level::Cutoff, ..}))
+        ));
+    }
+
+    #[test]
+    fn driver_writes_speed_and_turn() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        md23.drive(1.0, -1.0);
+        let states = wait_for_states(&mut md23);
+        assert!(states.iter().any(|s| matches!(s, State::Normal{..})));
+        assert_eq!(sim.mode(), 2);
+        assert_eq!(sim.speeds(), (255, 1));
+        md23.stop();
+        wait_for_states(&mut md23);
+        assert_eq!(sim.speeds(), (128, 128));
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn drive_command_encoding() {
+        assert_eq!(DriveCommand::UnsignedWheels{left: 1.0, right: -1.0}.encode(), (255, 1));
+        assert_eq!(DriveCommand::UnsignedWheels{left: 0.0, right: 0.5}.encode(), (128, 192));
+        assert_eq!(DriveCommand::SignedWheels{left: 1.0, right: -1.0}.encode(), (127, 129));
+        assert_eq!(DriveCommand::SignedWheels{left: 0.0, right: -0.5}.encode(), (0, 192));
+        assert_eq!(DriveCommand::UnsignedSpeedTurn{speed: 0.0, turn: 0.0}.encode(), (128, 128));
+        assert_eq!(DriveCommand::SignedSpeedTurn{speed: 0.5, turn: -0.25}.encode(), (64, 224));
+        // out of range values are clamped
+        assert_eq!(DriveCommand::SignedWheels{left: 2.0, right: -3.0}.encode(), (127, 129));
+    }
+
+    #[test]
+    fn drive_command_modes() {
+        for mode in [Mode::UnsignedWheels, Mode::SignedWheels, Mode::UnsignedSpeedTurn, Mode::SignedSpeedTurn].iter() {
+            assert_eq!(DriveCommand::stop(*mode).mode(), *mode);
+        }
+    }
+
+    #[test]
+    fn driver_switches_modes() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        md23.drive_wheels(-1.0, 1.0);
+        wait_for_states(&mut md23);
+        assert_eq!(sim.mode(), 1);
+        assert_eq!(sim.motor_outputs(), (-1.0, 1.0));
+
+        md23.command(DriveCommand::SignedSpeedTurn{speed: 0.5, turn: 0.0});
+        wait_for_states(&mut md23);
+        assert_eq!(sim.mode(), 3);
+        assert_eq!(sim.speeds(), (64, 0));
+
+        // stopping doesn't change the mode
+        md23.stop();
+        wait_for_states(&mut md23);
+        assert_eq!(sim.mode(), 3);
+        assert_eq!(sim.motor_outputs(), (0.0, 0.0));
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn reading_decode() {
+        let buffer = [0x00, 0x00, 0x01, 0x02, 0xff, 0xff, 0xff, 0xfe, 121, 25, 3];
+        assert_eq!(Reading::decode(&buffer), Reading{
+            enc1: 0x0102,
+            enc2: (-2i32) as u32,
+            voltage: 12.1,
+            current1: 2.5,
+            current2: 0.3,
+        });
+    }
+
+    #[test]
+    fn compute_state_uses_one_transaction() {
+        let sim = MD23Simulator::new();
+        sim.set_encoders(1000, 2000);
+        let mut bus = CountingBus{sim, transactions: 0};
+        let state = MD23Driver::compute_state(&mut bus, &mut battery(), &BOARD, &State::Error(DriverError::Nack)).unwrap();
+        assert_eq!(bus.transactions, 1);
+        match state {
+            State::Normal{enc1, enc2, voltage, ..} => {
+                assert_eq!(enc1, 1000);
+                assert_eq!(enc2, 2000);
+                assert_eq!(voltage, 12.0);
+            },
+            _ => panic!("expected normal state"),
+        }
+    }
+
+    #[test]
+    fn compute_state_reports_currents_and_board() {
+        let mut sim = MD23Simulator::new();
+        sim.set_currents(2.5, 0.3);
+        match MD23Driver::compute_state(&mut sim, &mut battery(), &BOARD, &State::Error(DriverError::Nack)).unwrap() {
+            State::Normal{current1, current2, revision, acceleration, ..} => {
+                assert_eq!(current1, 2.5);
+                assert_eq!(current2, 0.3);
+                assert_eq!(revision, BOARD.revision);
+                assert_eq!(acceleration, BOARD.acceleration);
+            },
+            _ => panic!("expected normal state"),
+        }
+    }
+
+    #[test]
+    fn driver_configures_acceleration() {
+        let sim = MD23Simulator::with_revision(7);
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, 2));
+        let states = wait_for_states(&mut md23);
+        assert_eq!(sim.acceleration(), 2);
+        match states.last() {
+            Some(State::Normal{revision, acceleration, ..}) => {
+                assert_eq!(*revision, 7);
+                assert_eq!(*acceleration, 2);
+            },
+            _ => panic!("expected normal state"),
+        }
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+
+        // out of range rates are clamped
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, 200));
+        wait_for_states(&mut md23);
+        assert_eq!(sim.acceleration(), MD23_MAX_ACCELERATION);
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn compute_state_classifies_errors() {
+        let mut sim = MD23Simulator::new();
+        sim.fail_transactions(1, SimulatorError::Timeout);
+        let previous = State::Error(DriverError::Nack);
+        assert!(matches!(MD23Driver::compute_state(&mut sim, &mut battery(), &BOARD, &previous), Err(DriverError::Timeout)));
+        sim.set_voltage(0.0);
+        assert!(matches!(MD23Driver::compute_state(&mut sim, &mut battery(), &BOARD, &previous), Err(DriverError::ImplausibleReading)));
+    }
+
+    #[test]
+    fn compute_state_rejects_encoder_jumps() {
+        let mut sim = MD23Simulator::new();
+        sim.set_encoders(1_000_000, 0);
+        let previous = MD23Driver::compute_state(&mut sim, &mut battery(), &BOARD, &State::Error(DriverError::Nack)).unwrap();
+        sim.set_encoders(0, 0);
+        assert!(matches!(MD23Driver::compute_state(&mut sim, &mut battery(), &BOARD, &previous), Err(DriverError::ImplausibleReading)));
+    }
+
+    #[test]
+    fn retry_backoff_doubles_up_to_maximum() {
+        let retry = RetryPolicy{
+            attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+        };
+        assert_eq!(retry.backoff(1), Duration::from_millis(100));
+        assert_eq!(retry.backoff(2), Duration::from_millis(200));
+        assert_eq!(retry.backoff(3), Duration::from_millis(400));
+        assert_eq!(retry.backoff(4), Duration::from_millis(500));
+        assert_eq!(retry.backoff(100), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn driver_recovers_from_bus_glitch() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, 7));
+        wait_for_states(&mut md23);
+        // the board browns out and misses some transfers
+        sim.power_cycle();
+        sim.fail_transactions(2, SimulatorError::Nack);
+        let states = wait_for_states(&mut md23);
+        let error = states.iter().position(|s| matches!(s, State::Error(DriverError::Nack)));
+        let normal = states.iter().rposition(|s| matches!(s, State::Normal{..}));
+        assert!(error.is_some());
+        assert!(normal > error);
+        // the board got re-initialised
+        assert_eq!(sim.mode(), 2);
+        assert_eq!(sim.acceleration(), 7);
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn driver_gives_up_after_retries() {
+        let sim = MD23Simulator::new();
+        sim.fail_transactions(usize::MAX, SimulatorError::Timeout);
+        let mut settings = MD23Settings::new(3, MD23_DEFAULT_ACCELERATION);
+        settings.retry = RetryPolicy{
+            attempts: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        };
+        let mut md23 = MD23Driver::with_bus(sim.clone(), settings);
+        let states = wait_for_states(&mut md23);
+        assert_eq!(states.len(), 3);
+        assert!(states.iter().all(|s| matches!(s, State::Error(DriverError::Timeout))));
+        // the thread is gone, but this doesn't hang
+        md23.drive(1.0, 0.0);
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Err(ShutdownError::NotConfirmed));
+    }
+
+    #[test]
+    fn control_command_encoding() {
+        assert_eq!(ControlCommand::ResetEncoders.encode(), 0x20);
+        assert_eq!(ControlCommand::SpeedRegulation(false).encode(), 0x30);
+        assert_eq!(ControlCommand::SpeedRegulation(true).encode(), 0x31);
+        assert_eq!(ControlCommand::MotorTimeout(false).encode(), 0x32);
+        assert_eq!(ControlCommand::MotorTimeout(true).encode(), 0x33);
+    }
+
+    #[test]
+    fn driver_resets_encoders_without_jump() {
+        let sim = MD23Simulator::new();
+        sim.set_encoders(100_000, -100_000);
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        wait_for_states(&mut md23);
+        md23.reset_encoders();
+        let states = wait_for_states(&mut md23);
+        assert_eq!(sim.encoders(), (0, 0));
+        for state in states.iter() {
+            match state {
+                State::Normal{enc1, enc2, diff1, diff2, ..} => {
+                    assert_eq!((*enc1, *enc2), (0, 0));
+                    assert_eq!((*diff1, *diff2), (0, 0));
+                },
+                _ => panic!("expected normal state"),
+            }
+        }
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn driver_toggles_regulation_and_timeout() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        md23.set_speed_regulation(false);
+        md23.set_motor_timeout(false);
+        wait_for_states(&mut md23);
+        assert!(!sim.speed_regulation());
+        assert!(!sim.motor_timeout());
+        md23.set_motor_timeout(true);
+        wait_for_states(&mut md23);
+        assert!(sim.motor_timeout());
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn watchdog_ramps_down_overdue_command() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut watchdog = Watchdog::new(ms(200), ms(100));
+        // nothing to do without a command
+        assert_eq!(watchdog.check(start + ms(1000)), None);
+
+        let command = DriveCommand::SignedWheels{left: 1.0, right: -0.5};
+        watchdog.renew(command, start);
+        assert_eq!(watchdog.check(start + ms(100)), None);
+        assert_eq!(watchdog.check(start + ms(250)), Some((command.scaled(0.5), true)));
+        assert_eq!(watchdog.check(start + ms(275)), Some((command.scaled(0.25), false)));
+        assert_eq!(watchdog.check(start + ms(300)), Some((DriveCommand::stop(Mode::SignedWheels), false)));
+        assert_eq!(watchdog.check(start + ms(400)), None);
+
+        // a fresh command re-arms it
+        watchdog.renew(command, start + ms(500));
+        assert_eq!(watchdog.check(start + ms(650)), None);
+        assert!(matches!(watchdog.check(start + ms(750)), Some((_, true))));
+
+        // and stopping disarms it
+        watchdog.renew(DriveCommand::stop(Mode::SignedWheels), start + ms(800));
+        assert_eq!(watchdog.check(start + ms(2000)), None);
+    }
+
+    #[test]
+    fn driver_stops_motors_without_fresh_commands() {
+        let sim = MD23Simulator::new();
+        let mut settings = MD23Settings::new(3, MD23_DEFAULT_ACCELERATION);
+        settings.command_timeout = Some(Duration::from_millis(200));
+        settings.timeout_ramp = Duration::from_millis(100);
+        let mut md23 = MD23Driver::with_bus(sim.clone(), settings);
+        md23.drive(1.0, 0.0);
+        let mut states = wait_for_states(&mut md23);
+        states.extend(wait_for_states(&mut md23));
+        assert_eq!(sim.speeds(), (128, 128));
+        assert_eq!(states.iter().filter(|s| matches!(s, State::CommandTimeout)).count(), 1);
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn driver_keeps_driving_without_command_timeout() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        md23.drive(1.0, 0.0);
+        wait_for_states(&mut md23);
+        let states = wait_for_states(&mut md23);
+        assert_eq!(sim.speeds(), (255, 128));
+        assert!(!states.iter().any(|s| matches!(s, State::CommandTimeout)));
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn state_telemetry() {
+        let battery = battery().update(12.0, 2.0, Instant::now());
+        let state = State::Normal{
+            when: Instant::now(),
+            voltage: 12.0,
+            enc1: 1000,
+            enc2: 2000,
+            diff1: 10,
+            diff2: -20,
+            speed1: 0.5,
+            speed2: -1.0,
+            current1: 1.5,
+            current2: 0.5,
+            revision: 1,
+            acceleration: 5,
+            battery,
+        };
+        match state.telemetry().as_slice() {
+            [Telemetry::Wheels(sample), Telemetry::Battery(status)] => {
+                assert_eq!((sample.left_steps, sample.right_steps), (10, -20));
+                assert_eq!((sample.left_speed, sample.right_speed), (0.5, -1.0));
+                assert_eq!((sample.left_current, sample.right_current), (Some(1.5), Some(0.5)));
+                assert_eq!(sample.voltage, 12.0);
+                assert_eq!(sample.steps_per_revolution, 360.0);
+                assert_eq!(*status, battery);
+            },
+            other => panic!("unexpected telemetry {:?}", other),
+        }
+        assert_eq!(
+            State::LowVoltage(battery).telemetry(),
+            vec![Telemetry::Battery(battery), Telemetry::Fault(Fault::LowVoltage)]
+        );
+        assert_eq!(State::CommandTimeout.telemetry(), vec![Telemetry::Fault(Fault::CommandTimeout)]);
+        assert_eq!(
+            State::Error(DriverError::Timeout).telemetry(),
+            vec![Telemetry::Fault(Fault::Communication("MD23 transfer timed out".to_string()))]
+        );
+    }
+
+    #[test]
+    fn driver_as_motor_controller() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        let motors: &mut dyn MotorController = &mut md23;
+        assert_eq!(motors.battery_voltage(), None);
+        motors.set_wheel_speeds(1.0, -1.0);
+        thread::sleep(MD23_POLL_INTERVAL * 3);
+        let telemetry = motors.telemetry();
+        assert!(telemetry.iter().any(|t| matches!(t, Telemetry::Wheels(_))));
+        assert_eq!(motors.battery_voltage(), Some(12.0));
+        assert_eq!(sim.mode(), 1);
+        assert_eq!(sim.motor_outputs(), (1.0, -1.0));
+        motors.stop();
+        thread::sleep(MD23_POLL_INTERVAL * 3);
+        assert_eq!(sim.motor_outputs(), (0.0, 0.0));
+        assert_eq!(motors.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn driver_reports_low_voltage() {
+        let sim = MD23Simulator::new();
+        sim.set_voltage(9.0);
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        let states = wait_for_states(&mut md23);
+        assert!(states.iter().any(|s| matches!(s, State::LowVoltage(_))));
+    }
+
+    #[test]
+    fn driver_recovers_from_low_voltage() {
+        let sim = MD23Simulator::new();
+        let mut settings = MD23Settings::new(3, MD23_DEFAULT_ACCELERATION);
+        settings.battery.filter = Duration::from_secs(0);
+        let mut md23 = MD23Driver::with_bus(sim.clone(), settings);
+        md23.drive(0.5, 0.0);
+        wait_for_states(&mut md23);
+        assert_ne!(sim.speeds(), (128, 128));
+
+        sim.set_voltage(9.5);
+        let states = wait_for_states(&mut md23);
+        assert!(matches!(states.last(), Some(State::LowVoltage(_))));
+        assert_eq!(sim.speeds(), (128, 128));
+        // drive commands are ignored meanwhile
+        md23.drive(0.5, 0.0);
+        wait_for_states(&mut md23);
+        assert_eq!(sim.speeds(), (128, 128));
+
+        // not enough to leave the cutoff
+        sim.set_voltage(10.0);
+        let states = wait_for_states(&mut md23);
+        assert!(matches!(states.last(), Some(State::LowVoltage(_))));
+
+        sim.set_voltage(10.8);
+        let states = wait_for_states(&mut md23);
+        match states.last() {
+            Some(State::Normal{battery, ..}) => assert_eq!(battery.level, Level::Warning),
+            other => panic!("expected normal state, got {:?}", other),
+        }
+        md23.drive(0.5, 0.0);
+        wait_for_states(&mut md23);
+        assert_ne!(sim.speeds(), (128, 128));
+    }
+
+    #[test]
+    fn shutdown_stops_motors() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        md23.drive(0.5, 0.0);
+        wait_for_states(&mut md23);
+        assert_ne!(sim.speeds(), (128, 128));
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+        assert_eq!(sim.speeds(), (128, 128));
+    }
+
+    #[test]
+    fn repeated_shutdown_reports_same_result() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+    }
+
+    #[test]
+    fn drop_stops_motors() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        md23.drive(0.5, 0.0);
+        wait_for_states(&mut md23);
+        assert_ne!(sim.speeds(), (128, 128));
+        drop(md23);
+        assert_eq!(sim.speeds(), (128, 128));
+    }
+
+    #[test]
+    fn shutdown_during_low_voltage() {
+        let sim = MD23Simulator::new();
+        let mut md23 = MD23Driver::with_bus(sim.clone(), MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        md23.drive(0.5, 0.0);
+        wait_for_states(&mut md23);
+        sim.set_voltage(9.0);
+        wait_for_states(&mut md23);
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+        assert_eq!(sim.speeds(), (128, 128));
+    }
+
+    #[test]
+    fn shutdown_interrupts_backoff() {
+        let sim = MD23Simulator::new();
+        let mut settings = MD23Settings::new(3, MD23_DEFAULT_ACCELERATION);
+        settings.retry.initial_backoff = Duration::from_secs(60);
+        let mut md23 = MD23Driver::with_bus(sim.clone(), settings);
+        wait_for_states(&mut md23);
+        sim.fail_transactions(1, SimulatorError::Nack);
+        wait_for_states(&mut md23);
+        let start = Instant::now();
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Ok(()));
+        assert!(start.elapsed() < SHUTDOWN_TIMEOUT);
+    }
+
+    #[test]
+    fn shutdown_without_working_bus_is_not_confirmed() {
+        let sim = MD23Simulator::new();
+        let mut settings = MD23Settings::new(3, MD23_DEFAULT_ACCELERATION);
+        settings.retry.initial_backoff = Duration::from_secs(60);
+        let mut md23 = MD23Driver::with_bus(sim.clone(), settings);
+        sim.fail_transactions(usize::MAX, SimulatorError::Timeout);
+        wait_for_states(&mut md23);
+        assert_eq!(md23.shutdown(SHUTDOWN_TIMEOUT), Err(ShutdownError::NotConfirmed));
+    }
+
+    // A bus whose transfers hang for a long time
+    struct StuckBus;
+
+    impl Bus for StuckBus {
+        type Error = SimulatorError;
+
+        fn read_register(&mut self, _register: u8) -> Result<u8, Self::Error> {
+            thread::sleep(Duration::from_secs(2));
+            Err(SimulatorError::Timeout)
+        }
+
+        fn write_register(&mut self, _register: u8, _value: u8) -> Result<(), Self::Error> {
+            thread::sleep(Duration::from_secs(2));
+            Err(SimulatorError::Timeout)
+        }
+
+        fn classify(error: Self::Error) -> DriverError {
+            MD23Simulator::classify(error)
+        }
+    }
+
+    #[test]
+    fn shutdown_times_out_on_stuck_bus() {
+        let mut md23 = MD23Driver::with_bus(StuckBus, MD23Settings::new(3, MD23_DEFAULT_ACCELERATION));
+        let start = Instant::now();
+        assert_eq!(md23.shutdown(Duration::from_millis(200)), Err(ShutdownError::Timeout));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
 
     #[test]
     fn encoder_diff_simple() {
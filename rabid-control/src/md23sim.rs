@@ -0,0 +1,454 @@
+// An in-memory stand-in for the MD23 board.
+//
+// It answers register reads and writes the same way
+// the real controller does, so the MD23Driver can be
+// run on a machine without an I2C bus. The simulator
+// is cheaply cloneable, all clones share the same
+// register map. That way a test can hand one clone
+// to the driver and use another to inspect and
+// manipulate the "hardware".
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::md23::{
+    Bus, DriverError,
+    MD23_SPEED1, MD23_SPEED2, MD23_MODE, MD23_ENC1, MD23_ENC2,
+    MD23_VOLTAGE, MD23_CURRENT1, MD23_CURRENT2, MD23_REVISION, MD23_ACCELERATION,
+    MD23_DEFAULT_ACCELERATION, MD23_COMMAND, MD23_CMD_RESET_ENCODERS,
+    MD23_CMD_DISABLE_SPEED_REGULATION, MD23_CMD_ENABLE_SPEED_REGULATION,
+    MD23_CMD_DISABLE_TIMEOUT, MD23_CMD_ENABLE_TIMEOUT,
+    MD23_ENCODER_STEPS_PER_REVOLUTION,
+};
+
+// How fast the wheels turn at full speed, in
+// revolutions per second.
+const MAX_REVOLUTIONS_PER_SECOND: f64 = 3.0;
+// The motors stop after this many seconds without
+// a write to the speed registers, unless disabled.
+const MOTOR_TIMEOUT: f64 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimulatorError {
+    UnknownRegister(u8),
+    ReadOnlyRegister(u8),
+    // Injected through fail_transactions
+    Nack,
+    Timeout,
+}
+
+impl fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulatorError::UnknownRegister(register) => write!(f, "unknown MD23 register {}", register),
+            SimulatorError::ReadOnlyRegister(register) => write!(f, "MD23 register {} is read only", register),
+            SimulatorError::Nack => write!(f, "simulated NACK"),
+            SimulatorError::Timeout => write!(f, "simulated timeout"),
+        }
+    }
+}
+
+impl std::error::Error for SimulatorError {}
+
+struct Registers
+{
+    speed1: u8,
+    speed2: u8,
+    mode: u8,
+    voltage: u8,
+    current1: u8,
+    current2: u8,
+    revision: u8,
+    acceleration: u8,
+    // The encoders are kept with sub-step precision,
+    // so integrating small time steps doesn't lose counts.
+    enc1: f64,
+    enc2: f64,
+    // Reading the most significant encoder byte
+    // captures the count, the remaining three bytes
+    // are served from the capture.
+    latched_enc1: u32,
+    latched_enc2: u32,
+    last_command: Option<u8>,
+    speed_regulation: bool,
+    motor_timeout: bool,
+    // Seconds since the speed registers were written
+    idle: f64,
+    // The next n transactions fail with the given error
+    failures: Option<(usize, SimulatorError)>,
+}
+
+#[derive(Clone)]
+pub struct MD23Simulator
+{
+    registers: Arc<Mutex<Registers>>,
+}
+
+impl Default for MD23Simulator {
+    fn default() -> Self {
+        MD23Simulator::new()
+    }
+}
+
+impl MD23Simulator {
+
+    pub fn new() -> MD23Simulator
+    {
+        MD23Simulator::with_revision(1)
+    }
+
+    // Simulate a board running the given firmware revision.
+    pub fn with_revision(revision: u8) -> MD23Simulator
+    {
+        MD23Simulator{
+            registers: Arc::new(Mutex::new(Registers{
+                speed1: 128,
+                speed2: 128,
+                mode: 0,
+                voltage: 120,
+                current1: 0,
+                current2: 0,
+                revision,
+                acceleration: MD23_DEFAULT_ACCELERATION,
+                enc1: 0.0,
+                enc2: 0.0,
+                latched_enc1: 0,
+                latched_enc2: 0,
+                last_command: None,
+                speed_regulation: true,
+                motor_timeout: true,
+                idle: 0.0,
+                failures: None,
+            }))
+        }
+    }
+
+    // Let the next count bus transactions fail.
+    pub fn fail_transactions(&self, count: usize, error: SimulatorError)
+    {
+        self.registers.lock().unwrap().failures = Some((count, error));
+    }
+
+    // Like a brown-out: the configuration registers
+    // fall back to their power-on defaults, the
+    // encoders are cleared.
+    pub fn power_cycle(&self)
+    {
+        let mut registers = self.registers.lock().unwrap();
+        registers.speed1 = 128;
+        registers.speed2 = 128;
+        registers.mode = 0;
+        registers.acceleration = MD23_DEFAULT_ACCELERATION;
+        registers.enc1 = 0.0;
+        registers.enc2 = 0.0;
+    }
+
+    pub fn set_voltage(&self, voltage: f32)
+    {
+        self.registers.lock().unwrap().voltage = (voltage * 10.0).round() as u8;
+    }
+
+    pub fn set_currents(&self, current1: f32, current2: f32)
+    {
+        let mut registers = self.registers.lock().unwrap();
+        registers.current1 = (current1 * 10.0).round() as u8;
+        registers.current2 = (current2 * 10.0).round() as u8;
+    }
+
+    pub fn set_encoders(&self, enc1: i32, enc2: i32)
+    {
+        let mut registers = self.registers.lock().unwrap();
+        registers.enc1 = enc1 as f64;
+        registers.enc2 = enc2 as f64;
+    }
+
+    pub fn encoders(&self) -> (i32, i32)
+    {
+        let registers = self.registers.lock().unwrap();
+        (registers.enc1 as i32, registers.enc2 as i32)
+    }
+
+    pub fn speeds(&self) -> (u8, u8)
+    {
+        let registers = self.registers.lock().unwrap();
+        (registers.speed1, registers.speed2)
+    }
+
+    pub fn mode(&self) -> u8
+    {
+        self.registers.lock().unwrap().mode
+    }
+
+    pub fn acceleration(&self) -> u8
+    {
+        self.registers.lock().unwrap().acceleration
+    }
+
+    pub fn last_command(&self) -> Option<u8>
+    {
+        self.registers.lock().unwrap().last_command
+    }
+
+    pub fn speed_regulation(&self) -> bool
+    {
+        self.registers.lock().unwrap().speed_regulation
+    }
+
+    pub fn motor_timeout(&self) -> bool
+    {
+        self.registers.lock().unwrap().motor_timeout
+    }
+
+    // The motor outputs the current register contents
+    // translate to, normalized to -1.0..1.0.
+    pub fn motor_outputs(&self) -> (f64, f64)
+    {
+        self.registers.lock().unwrap().motor_outputs()
+    }
+
+    // Let the given amount of time pass, turning
+    // the wheels according to the motor outputs.
+    pub fn step(&self, seconds: f64)
+    {
+        let mut registers = self.registers.lock().unwrap();
+        registers.idle += seconds;
+        if registers.motor_timeout && registers.idle >= MOTOR_TIMEOUT {
+            let stop = if registers.mode == 1 || registers.mode == 3 { 0 } else { 128 };
+            registers.speed1 = stop;
+            registers.speed2 = stop;
+        }
+        let (m1, m2) = registers.motor_outputs();
+        let steps_per_second = MAX_REVOLUTIONS_PER_SECOND * MD23_ENCODER_STEPS_PER_REVOLUTION as f64;
+        registers.enc1 += m1 * steps_per_second * seconds;
+        registers.enc2 += m2 * steps_per_second * seconds;
+    }
+}
+
+impl Registers {
+
+    fn transaction(&mut self) -> Result<(), SimulatorError>
+    {
+        match self.failures {
+            Some((count, error)) if count > 0 => {
+                self.failures = Some((count - 1, error));
+                Err(error)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn motor_outputs(&self) -> (f64, f64)
+    {
+        let unsigned = |v: u8| v as f64 - 128.0;
+        let signed = |v: u8| v as i8 as f64;
+        let (m1, m2) = match self.mode {
+            0 => (unsigned(self.speed1), unsigned(self.speed2)),
+            1 => (signed(self.speed1), signed(self.speed2)),
+            _ => {
+                let (speed, turn) = if self.mode == 2 {
+                    (unsigned(self.speed1), unsigned(self.speed2))
+                } else {
+                    (signed(self.speed1), signed(self.speed2))
+                };
+                // The turn value is subtracted from or added
+                // to the speed, depending on the direction.
+                if speed >= 0.0 {
+                    (speed - turn, speed + turn)
+                } else {
+                    (speed + turn, speed - turn)
+                }
+            }
+        };
+        let clamp = |v: f64| (v / 127.0).clamp(-1.0, 1.0);
+        (clamp(m1), clamp(m2))
+    }
+
+    fn encoder_byte(&mut self, register: u8) -> u8
+    {
+        let (latched, offset) = if register < MD23_ENC2 {
+            if register == MD23_ENC1 {
+                self.latched_enc1 = self.enc1 as i32 as u32;
+            }
+            (self.latched_enc1, register - MD23_ENC1)
+        } else {
+            if register == MD23_ENC2 {
+                self.latched_enc2 = self.enc2 as i32 as u32;
+            }
+            (self.latched_enc2, register - MD23_ENC2)
+        };
+        (latched >> (8 * (3 - offset))) as u8
+    }
+
+    fn read(&mut self, register: u8) -> Result<u8, SimulatorError>
+    {
+        match register {
+            MD23_SPEED1 => Ok(self.speed1),
+            MD23_SPEED2 => Ok(self.speed2),
+            r if (MD23_ENC1..MD23_VOLTAGE).contains(&r) => Ok(self.encoder_byte(r)),
+            MD23_VOLTAGE => Ok(self.voltage),
+            MD23_CURRENT1 => Ok(self.current1),
+            MD23_CURRENT2 => Ok(self.current2),
+            MD23_REVISION => Ok(self.revision),
+            MD23_ACCELERATION => Ok(self.acceleration),
+            MD23_MODE => Ok(self.mode),
+            r => Err(SimulatorError::UnknownRegister(r)),
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) -> Result<(), SimulatorError>
+    {
+        match register {
+            MD23_SPEED1 => {
+                self.speed1 = value;
+                self.idle = 0.0;
+            },
+            MD23_SPEED2 => {
+                self.speed2 = value;
+                self.idle = 0.0;
+            },
+            MD23_MODE => self.mode = value,
+            MD23_ACCELERATION => self.acceleration = value,
+            MD23_COMMAND => {
+                match value {
+                    MD23_CMD_RESET_ENCODERS => {
+                        self.enc1 = 0.0;
+                        self.enc2 = 0.0;
+                    },
+                    MD23_CMD_DISABLE_SPEED_REGULATION => self.speed_regulation = false,
+                    MD23_CMD_ENABLE_SPEED_REGULATION => self.speed_regulation = true,
+                    MD23_CMD_DISABLE_TIMEOUT => self.motor_timeout = false,
+                    MD23_CMD_ENABLE_TIMEOUT => self.motor_timeout = true,
+                    _ => {}
+                }
+                self.last_command = Some(value);
+            }
+            r if (MD23_ENC1..=MD23_REVISION).contains(&r) => return Err(SimulatorError::ReadOnlyRegister(r)),
+            r => return Err(SimulatorError::UnknownRegister(r)),
+        }
+        Ok(())
+    }
+}
+
+impl Bus for MD23Simulator {
+    type Error = SimulatorError;
+
+    fn read_register(&mut self, register: u8) -> Result<u8, SimulatorError>
+    {
+        let mut registers = self.registers.lock().unwrap();
+        registers.transaction()?;
+        registers.read(register)
+    }
+
+    fn write_register(&mut self, register: u8, value: u8) -> Result<(), SimulatorError>
+    {
+        let mut registers = self.registers.lock().unwrap();
+        registers.transaction()?;
+        registers.write(register, value)
+    }
+
+    // Holding the lock for the whole block makes
+    // this atomic with regard to step().
+    fn read_registers(&mut self, start: u8, buffer: &mut [u8]) -> Result<(), SimulatorError>
+    {
+        let mut registers = self.registers.lock().unwrap();
+        registers.transaction()?;
+        for (i, value) in buffer.iter_mut().enumerate() {
+            *value = registers.read(start + i as u8)?;
+        }
+        Ok(())
+    }
+
+    fn classify(error: SimulatorError) -> DriverError
+    {
+        match error {
+            SimulatorError::Nack => DriverError::Nack,
+            SimulatorError::Timeout => DriverError::Timeout,
+            _ => DriverError::Bus(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoder_bytes_are_big_endian() {
+        let mut sim = MD23Simulator::new();
+        sim.set_encoders(0x01020304, -1);
+        let bytes: Vec<u8> = (MD23_ENC1..MD23_VOLTAGE).map(|r| sim.read_register(r).unwrap()).collect();
+        assert_eq!(bytes, vec![1, 2, 3, 4, 255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn encoder_is_captured_on_first_byte() {
+        let mut sim = MD23Simulator::new();
+        sim.set_encoders(0x000000ff, 0);
+        assert_eq!(sim.read_register(MD23_ENC1).unwrap(), 0);
+        sim.set_encoders(0x00000100, 0);
+        // still served from the capture
+        assert_eq!(sim.read_register(MD23_ENC1 + 2).unwrap(), 0);
+        assert_eq!(sim.read_register(MD23_ENC1 + 3).unwrap(), 0xff);
+    }
+
+    #[test]
+    fn block_read_covers_consecutive_registers() {
+        let mut sim = MD23Simulator::new();
+        sim.set_encoders(0x01020304, 0x05060708);
+        sim.set_voltage(12.5);
+        let mut buffer = [0; 9];
+        sim.read_registers(MD23_ENC1, &mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, 3, 4, 5, 6, 7, 8, 125]);
+    }
+
+    #[test]
+    fn step_turns_wheels_in_speed_turn_mode() {
+        let mut sim = MD23Simulator::new();
+        sim.write_register(MD23_MODE, 2).unwrap();
+        sim.write_register(MD23_SPEED1, 255).unwrap();
+        sim.write_register(MD23_SPEED2, 128).unwrap();
+        sim.step(1.0);
+        let (enc1, enc2) = sim.encoders();
+        assert_eq!(enc1, 1080);
+        assert_eq!(enc2, 1080);
+    }
+
+    #[test]
+    fn command_resets_encoders() {
+        let mut sim = MD23Simulator::new();
+        sim.set_encoders(100, -100);
+        sim.write_register(MD23_COMMAND, MD23_CMD_RESET_ENCODERS).unwrap();
+        assert_eq!(sim.encoders(), (0, 0));
+        assert_eq!(sim.last_command(), Some(MD23_CMD_RESET_ENCODERS));
+    }
+
+    #[test]
+    fn motors_time_out() {
+        let mut sim = MD23Simulator::new();
+        sim.write_register(MD23_SPEED1, 255).unwrap();
+        sim.step(1.5);
+        assert_eq!(sim.speeds(), (255, 128));
+        sim.step(1.0);
+        assert_eq!(sim.speeds(), (128, 128));
+
+        sim.write_register(MD23_COMMAND, MD23_CMD_DISABLE_TIMEOUT).unwrap();
+        sim.write_register(MD23_SPEED1, 255).unwrap();
+        sim.step(5.0);
+        assert_eq!(sim.speeds(), (255, 128));
+    }
+
+    #[test]
+    fn injected_failures() {
+        let mut sim = MD23Simulator::new();
+        sim.fail_transactions(2, SimulatorError::Timeout);
+        assert_eq!(sim.read_register(MD23_MODE), Err(SimulatorError::Timeout));
+        assert_eq!(sim.write_register(MD23_MODE, 2), Err(SimulatorError::Timeout));
+        assert_eq!(sim.write_register(MD23_MODE, 2), Ok(()));
+        assert_eq!(sim.mode(), 2);
+    }
+
+    #[test]
+    fn encoders_are_read_only() {
+        let mut sim = MD23Simulator::new();
+        assert_eq!(sim.write_register(MD23_ENC1, 0), Err(SimulatorError::ReadOnlyRegister(MD23_ENC1)));
+    }
+}
@@ -0,0 +1,108 @@
+// What the rest of the stack needs from a motor board.
+//
+// The daemon, path following and the simulator only
+// talk to the motors through MotorController, so a
+// different board just needs another implementation.
+// The MD23Driver is the first one.
+use std::time::{Duration, Instant};
+
+use crate::battery::BatteryStatus;
+
+// Everything a backend reports about its wheels in one poll.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WheelSample
+{
+    pub when: Instant,
+    pub voltage: f32,
+    // Encoder steps since the previous sample
+    pub left_steps: i32,
+    pub right_steps: i32,
+    pub steps_per_revolution: f32,
+    // In revolutions per second, sign indicates direction
+    pub left_speed: f32,
+    pub right_speed: f32,
+    // In Ampere, if the board measures them
+    pub left_current: Option<f32>,
+    pub right_current: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault
+{
+    // The battery reached its cutoff level
+    LowVoltage,
+    // No fresh wheel speeds arrived in time,
+    // the motors are being stopped.
+    CommandTimeout,
+    // Talking to the board failed, with a
+    // human readable description.
+    Communication(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Telemetry
+{
+    Wheels(WheelSample),
+    Battery(BatteryStatus),
+    Fault(Fault),
+    Shutdown,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShutdownError
+{
+    // No confirmation within the timeout
+    Timeout,
+    // The backend went away without confirming
+    // that the motors stopped.
+    NotConfirmed,
+}
+
+pub trait MotorController
+{
+    // Normalized wheel power, from -1.0 full reverse
+    // to 1.0 full forward.
+    fn set_wheel_speeds(&mut self, left: f32, right: f32);
+    fn stop(&mut self);
+    // Everything reported since the last call.
+    fn telemetry(&mut self) -> Vec<Telemetry>;
+    // The last voltage reported, if any.
+    fn battery_voltage(&self) -> Option<f32>;
+    // Stops the motors and releases the board. Ok only
+    // if the backend confirmed the motors stopped.
+    fn shutdown(&mut self, timeout: Duration) -> Result<(), ShutdownError>;
+}
+
+// Turns a speed and a turn value into wheel powers,
+// the same way the MD23 does in its turn modes: the
+// turn is taken from the left and given to the right
+// wheel when driving forward, and the other way round
+// when reversing.
+pub fn mix(speed: f32, turn: f32) -> (f32, f32)
+{
+    let (left, right) = if speed >= 0.0 {
+        (speed - turn, speed + turn)
+    } else {
+        (speed + turn, speed - turn)
+    };
+    (left.clamp(-1.0, 1.0), right.clamp(-1.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_forward_and_reverse() {
+        assert_eq!(mix(0.5, 0.0), (0.5, 0.5));
+        assert_eq!(mix(0.5, 0.25), (0.25, 0.75));
+        assert_eq!(mix(-0.5, 0.25), (-0.25, -0.75));
+        assert_eq!(mix(0.0, 0.5), (-0.5, 0.5));
+    }
+
+    #[test]
+    fn mix_clamps() {
+        assert_eq!(mix(1.0, 0.5), (0.5, 1.0));
+        assert_eq!(mix(-1.0, 0.5), (-0.5, -1.0));
+    }
+}
@@ -0,0 +1,199 @@
+// Dead reckoning from the wheel encoders.
+//
+// Each wheel sample carries how far the encoders
+// moved since the previous one. Using the geometry
+// of the robot, this is turned into a distance
+// travelled and a change of heading, which is
+// integrated into a Pose.
+use std::f64::consts::PI;
+
+use crate::motor::{Telemetry, WheelSample};
+use crate::path::{Pose, Vector, Rotation};
+use crate::twowheel::TwoWheelRobot;
+
+pub struct Odometry
+{
+    robot: TwoWheelRobot,
+    pose: Pose,
+    // The encoder counts since the last reset. Kept in
+    // 64 bits, so they don't wrap around even on
+    // very long runs.
+    counts1: i64,
+    counts2: i64,
+}
+
+impl Odometry {
+
+    pub fn new(robot: TwoWheelRobot) -> Odometry
+    {
+        Odometry::with_pose(robot, Pose::origin())
+    }
+
+    pub fn with_pose(robot: TwoWheelRobot, pose: Pose) -> Odometry
+    {
+        Odometry{robot, pose, counts1: 0, counts2: 0}
+    }
+
+    pub fn pose(&self) -> Pose
+    {
+        self.pose
+    }
+
+    // The accumulated encoder counts of both wheels.
+    pub fn counts(&self) -> (i64, i64)
+    {
+        (self.counts1, self.counts2)
+    }
+
+    // Start integrating from the given pose, with
+    // the accumulated counts cleared.
+    pub fn reset(&mut self, pose: Pose)
+    {
+        self.pose = pose;
+        self.counts1 = 0;
+        self.counts2 = 0;
+    }
+
+    // Integrates the encoder movement of a wheel sample,
+    // all other telemetry is ignored. Returns the
+    // updated pose.
+    pub fn update(&mut self, telemetry: &Telemetry) -> Pose
+    {
+        if let Telemetry::Wheels(sample) = telemetry {
+            self.advance(sample);
+        }
+        self.pose
+    }
+
+    fn advance(&mut self, sample: &WheelSample)
+    {
+        self.counts1 += sample.left_steps as i64;
+        self.counts2 += sample.right_steps as i64;
+
+        let distance_per_step = PI * self.robot.wheeldiameter() / sample.steps_per_revolution as f64;
+        self.integrate(sample.left_steps as f64 * distance_per_step, sample.right_steps as f64 * distance_per_step);
+    }
+
+    // Both wheel distances in cm
+    fn integrate(&mut self, left: f64, right: f64)
+    {
+        let distance = (left + right) / 2.0;
+        let turn = (right - left) / self.robot.wheelbase();
+
+        // Within one sample the robot drives on a circular
+        // arc. The chord of that arc is slightly shorter
+        // than the arc itself, and points halfway into
+        // the turn.
+        let chord = if turn.abs() < 1e-9 {
+            distance
+        } else {
+            2.0 * distance / turn * (turn / 2.0).sin()
+        };
+        let heading = self.pose.heading();
+        let direction = Rotation::new(heading + turn / 2.0);
+        self.pose = Pose{
+            position: self.pose.position + direction.transform_vector(&Vector::new(chord, 0.0)),
+            rotation: Rotation::new(heading + turn),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::motor::Fault;
+
+    // One full wheel revolution covers exactly 36cm,
+    // so each encoder step is 1mm. And turning on
+    // the spot takes 10cm per quarter turn.
+    fn robot() -> TwoWheelRobot
+    {
+        TwoWheelRobot::new(40.0 / PI, 36.0 / PI)
+    }
+
+    fn sample(left_steps: i32, right_steps: i32) -> Telemetry
+    {
+        Telemetry::Wheels(WheelSample{
+            when: Instant::now(),
+            voltage: 12.0,
+            left_steps,
+            right_steps,
+            steps_per_revolution: 360.0,
+            left_speed: 0.0,
+            right_speed: 0.0,
+            left_current: None,
+            right_current: None,
+        })
+    }
+
+    fn assert_pose(pose: Pose, x: f64, y: f64, heading: f64)
+    {
+        assert!((pose.position - Vector::new(x, y)).norm() < 0.0001, "{:?}", pose);
+        assert!((pose.rotation.angle_to(&Rotation::new(heading))).abs() < 0.0001, "{:?}", pose);
+    }
+
+    #[test]
+    fn straight_line() {
+        let mut odometry = Odometry::new(robot());
+        odometry.update(&sample(100, 100));
+        odometry.update(&sample(50, 50));
+        assert_pose(odometry.pose(), 15.0, 0.0, 0.0);
+        odometry.update(&sample(-150, -150));
+        assert_pose(odometry.pose(), 0.0, 0.0, 0.0);
+    }
+
+    #[test]
+    fn turn_on_the_spot() {
+        let mut odometry = Odometry::new(robot());
+        odometry.update(&sample(-100, 100));
+        assert_pose(odometry.pose(), 0.0, 0.0, PI / 2.0);
+        odometry.update(&sample(200, -200));
+        assert_pose(odometry.pose(), 0.0, 0.0, -PI / 2.0);
+    }
+
+    #[test]
+    fn driving_a_circle() {
+        let mut odometry = Odometry::new(robot());
+        // a quarter left turn with radius 50cm
+        let radius = 50.0;
+        let half_wheelbase = robot().wheelbase() / 2.0;
+        let left = (radius - half_wheelbase) * PI / 2.0;
+        let right = (radius + half_wheelbase) * PI / 2.0;
+        // the result must not depend on the sample size
+        for samples in [1, 3, 100].iter() {
+            odometry.reset(Pose::origin());
+            for _ in 0..*samples {
+                odometry.integrate(left / *samples as f64, right / *samples as f64);
+            }
+            assert_pose(odometry.pose(), radius, radius, PI / 2.0);
+        }
+    }
+
+    #[test]
+    fn counts_do_not_wrap() {
+        let mut odometry = Odometry::new(robot());
+        for _ in 0..4 {
+            odometry.update(&sample(i32::MAX, i32::MIN));
+        }
+        assert_eq!(odometry.counts(), (4 * i32::MAX as i64, 4 * i32::MIN as i64));
+    }
+
+    #[test]
+    fn ignores_other_telemetry() {
+        let mut odometry = Odometry::new(robot());
+        odometry.update(&Telemetry::Fault(Fault::LowVoltage));
+        odometry.update(&Telemetry::Shutdown);
+        assert_eq!(odometry.pose(), Pose::origin());
+    }
+
+    #[test]
+    fn reset_to_pose() {
+        let mut odometry = Odometry::new(robot());
+        odometry.update(&sample(100, 120));
+        odometry.reset(Pose::new(10.0, 5.0, PI));
+        assert_eq!(odometry.counts(), (0, 0));
+        odometry.update(&sample(100, 100));
+        assert_pose(odometry.pose(), 0.0, 5.0, PI);
+    }
+}
@@ -1,5 +1,7 @@
 extern crate nalgebra as na;
 use na::{Vector2, Rotation2};
+use std::cmp::Ordering;
+use std::f64::consts::PI;
 use std::fmt;
 use std::time::Duration;
 use libm::fmin;
@@ -8,6 +10,97 @@ use serde::{Deserialize, Serialize};
 pub type Vector = Vector2<f64>;
 pub type Rotation = Rotation2<f64>;
 
+// The relative step used to estimate curvature
+const CURVATURE_STEP: f64 = 1e-6;
+// Segments without an analytic projection are
+// sampled this often, and the best sample is
+// refined with this many golden section steps.
+const PROJECTION_SAMPLES: usize = 32;
+const PROJECTION_ITERATIONS: usize = 60;
+// With a hint, projections only consider the
+// segments this close to it, in cm of extent.
+pub const PROJECTION_WINDOW: f64 = 50.0;
+
+// Where the robot is, and where it's heading.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose
+{
+    pub position: Vector,
+    pub rotation: Rotation,
+}
+
+impl Pose {
+
+    pub fn new(x: f64, y: f64, heading: f64) -> Pose
+    {
+        Pose{position: Vector::new(x, y), rotation: Rotation::new(heading)}
+    }
+
+    pub fn origin() -> Pose
+    {
+        Pose::new(0.0, 0.0, 0.0)
+    }
+
+    // In radians, counter-clockwise from the x-axis
+    pub fn heading(&self) -> f64
+    {
+        self.rotation.angle()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathError
+{
+    Empty,
+    NotANumber,
+    // Positions run from 0.0 to 1.0
+    OutOfRange(f64),
+    // The segment at the given index doesn't extend,
+    // or its geometry isn't finite.
+    InvalidSegment{index: usize},
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Empty => write!(f, "path has no segments"),
+            PathError::NotANumber => write!(f, "position is not a number"),
+            PathError::OutOfRange(position) => write!(f, "position {} is outside of 0.0..1.0", position),
+            PathError::InvalidSegment{index} => write!(f, "segment {} is empty or not finite", index),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+// Which way the robot drives along a segment.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Direction
+{
+    Forward,
+    Reverse,
+}
+
+impl Direction {
+
+    pub fn reversed(&self) -> Direction
+    {
+        match self {
+            Direction::Forward => Direction::Reverse,
+            Direction::Reverse => Direction::Forward,
+        }
+    }
+
+    // 1.0 driving forward, -1.0 in reverse
+    pub fn sign(&self) -> f64
+    {
+        match self {
+            Direction::Forward => 1.0,
+            Direction::Reverse => -1.0,
+        }
+    }
+}
+
 fn signum(n: f64) -> f64
 {
     if n > 0.0 {
@@ -20,8 +113,144 @@ fn signum(n: f64) -> f64
 }
 
 pub trait PathSegment {
+    // The distance the robot covers
     fn length(&self) -> f64;
+    // How far along the path the segment reaches, which
+    // is what the position in at is relative to. For
+    // segments that move the robot it's their length,
+    // turning on the spot uses the distance the wheels
+    // travel instead.
+    fn extent(&self) -> f64
+    {
+        self.length()
+    }
     fn at(&self, position: f64) -> (Vector, Rotation);
+    // The direction the robot faces, as a unit vector
+    fn tangent(&self, position: f64) -> Vector
+    {
+        self.at(position).1.transform_vector(&Vector::new(1.0, 0.0))
+    }
+    // In radians, relative to the start of the segment.
+    // Segments that know it don't wrap the angle, so
+    // full turns can be counted.
+    fn heading(&self, position: f64) -> f64
+    {
+        self.at(position).1.angle()
+    }
+    // The signed change of heading per cm driven,
+    // positive turning left. Segments without an
+    // analytic form get it estimated from at.
+    fn curvature(&self, position: f64) -> f64
+    {
+        let length = self.length();
+        if length.is_nan() || length <= 0.0 {
+            return 0.0;
+        }
+        let before = (position - CURVATURE_STEP).max(0.0);
+        let after = (position + CURVATURE_STEP).min(1.0);
+        let (_, start) = self.at(before);
+        let (_, end) = self.at(after);
+        (end * start.inverse()).angle() / ((after - before) * length)
+    }
+    // The change of heading per cm of extent. The same
+    // as the curvature, except when turning on the spot.
+    fn heading_rate(&self, position: f64) -> f64
+    {
+        self.curvature(position)
+    }
+    // The relative position of the point on the segment
+    // closest to the given one, in the frame of the
+    // segment like the results of at.
+    fn project(&self, point: &Vector) -> f64
+    {
+        project_numerically(self, point)
+    }
+    // How the segment is stored in a path file,
+    // None for segments path files can't describe.
+    fn spec(&self) -> Option<SegmentSpec>
+    {
+        None
+    }
+    // The stretches of the segment driven in one
+    // direction, given by their relative start.
+    fn runs(&self) -> Vec<(f64, Direction)>
+    {
+        vec![(0.0, Direction::Forward)]
+    }
+    // The stretches of the segment where the robot
+    // turns on the spot, as relative start and end.
+    // Segments changing only the heading are one.
+    fn spins(&self) -> Vec<(f64, f64)>
+    {
+        if self.length() == 0.0 && self.extent() > 0.0 {
+            vec![(0.0, 1.0)]
+        }
+        else
+        {
+            Vec::new()
+        }
+    }
+}
+
+// A segment as plain data, tagged with the
+// segment type. This is what path files are
+// made of, see pathfile.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SegmentSpec
+{
+    Linear{length: f64},
+    Circle{radius: f64, arc: f64},
+    Rotate{angle: f64, radius: f64},
+    Clothoid{length: f64, start_curvature: f64, end_curvature: f64},
+    // The four control points as drawn, in cm
+    Bezier{points: [[f64; 2]; 4]},
+    // Drives the segment backwards
+    Reverse{segment: Box<SegmentSpec>},
+    Compound{segments: Vec<SegmentSpec>},
+}
+
+// Samples the segment for the closest point, and
+// narrows down around it with a golden section search.
+fn project_numerically<S: PathSegment + ?Sized>(segment: &S, point: &Vector) -> f64
+{
+    let distance = |position: f64| (segment.at(position).0 - point).norm();
+    let samples = PROJECTION_SAMPLES as f64;
+    let best = (0..=PROJECTION_SAMPLES)
+        .map(|sample| sample as f64 / samples)
+        .min_by(|a, b| distance(*a).partial_cmp(&distance(*b)).unwrap_or(Ordering::Equal))
+        .unwrap_or(0.0);
+    let mut low = (best - 1.0 / samples).max(0.0);
+    let mut high = (best + 1.0 / samples).min(1.0);
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    for _ in 0..PROJECTION_ITERATIONS {
+        let a = high - ratio * (high - low);
+        let b = low + ratio * (high - low);
+        if distance(a) < distance(b) {
+            high = b;
+        }
+        else
+        {
+            low = a;
+        }
+    }
+    (low + high) / 2.0
+}
+
+// Where a pose is relative to a path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projection
+{
+    // The relative position along the path
+    pub position: f64,
+    // The closest point on the path
+    pub point: Vector,
+    // The distance from the path, positive
+    // when left of it
+    pub cross_track: f64,
+    // The heading of the pose minus the heading
+    // of the path, within -pi..pi
+    pub heading_error: f64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -33,7 +262,7 @@ pub struct LinearSegment
 impl LinearSegment {
     pub fn new(length: f64) -> LinearSegment
     {
-        LinearSegment{length: length}
+        LinearSegment{length}
     }
 }
 
@@ -41,13 +270,44 @@ impl PathSegment for LinearSegment
 {
     fn length(&self) -> f64
     {
-        return self.length;
+        self.length
     }
 
     fn at(&self, position: f64) -> (Vector, Rotation)
     {
         (Vector::new(position * self.length, 0.0), Rotation::new(0.0))
     }
+
+    fn tangent(&self, _position: f64) -> Vector
+    {
+        Vector::new(1.0, 0.0)
+    }
+
+    fn heading(&self, _position: f64) -> f64
+    {
+        0.0
+    }
+
+    fn curvature(&self, _position: f64) -> f64
+    {
+        0.0
+    }
+
+    fn project(&self, point: &Vector) -> f64
+    {
+        if self.length > 0.0 {
+            (point[0] / self.length).clamp(0.0, 1.0)
+        }
+        else
+        {
+            0.0
+        }
+    }
+
+    fn spec(&self) -> Option<SegmentSpec>
+    {
+        Some(SegmentSpec::Linear{length: self.length})
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -68,7 +328,7 @@ impl PathSegment for CircleSegment
 {
     fn length(&self) -> f64
     {
-        return self.arc.abs() * self.radius;
+        self.arc.abs() * self.radius
     }
 
     fn at(&self, position: f64) -> (Vector, Rotation)
@@ -83,20 +343,219 @@ impl PathSegment for CircleSegment
         let v = r.transform_vector(&v) - v;
         (v, r)
     }
+
+    fn tangent(&self, position: f64) -> Vector
+    {
+        let heading = self.heading(position);
+        Vector::new(heading.cos(), heading.sin())
+    }
+
+    fn heading(&self, position: f64) -> f64
+    {
+        self.arc * position
+    }
+
+    fn curvature(&self, _position: f64) -> f64
+    {
+        signum(self.arc) / self.radius
+    }
+
+    fn project(&self, point: &Vector) -> f64
+    {
+        let sign = signum(self.arc);
+        let centre = Vector::new(0.0, self.radius * sign);
+        let from_centre = point - centre;
+        if from_centre.norm() == 0.0 {
+            return 0.0;
+        }
+        // how far around the circle the point
+        // is, in the direction we drive
+        let start = -centre;
+        let cross = start[0] * from_centre[1] - start[1] * from_centre[0];
+        let mut angle = sign * cross.atan2(start.dot(&from_centre));
+        if angle < 0.0 {
+            angle += 2.0 * PI;
+        }
+        let arc = self.arc.abs();
+        if angle <= arc {
+            return angle / arc;
+        }
+        // beyond the arc, one of the ends is closest
+        let (end, _) = self.at(1.0);
+        if (point - end).norm() < point.norm() {
+            1.0
+        }
+        else
+        {
+            0.0
+        }
+    }
+
+    fn spec(&self) -> Option<SegmentSpec>
+    {
+        Some(SegmentSpec::Circle{radius: self.radius, arc: self.arc})
+    }
+}
+
+// Turns the robot on the spot, changing its heading
+// without moving it. The radius is the distance of
+// the wheels from the centre of rotation, so half
+// the wheelbase. The segment extends as far as the
+// wheels travel while turning.
+#[derive(Serialize, Deserialize)]
+pub struct RotateSegment
+{
+    angle: f64,
+    radius: f64,
+}
+
+impl RotateSegment {
+    pub fn new(angle: f64, radius: f64) -> RotateSegment
+    {
+        RotateSegment{angle, radius}
+    }
+}
+
+impl PathSegment for RotateSegment
+{
+    fn length(&self) -> f64
+    {
+        0.0
+    }
+
+    fn extent(&self) -> f64
+    {
+        self.angle.abs() * self.radius
+    }
+
+    fn at(&self, position: f64) -> (Vector, Rotation)
+    {
+        (Vector::new(0.0, 0.0), Rotation::new(self.angle * position))
+    }
+
+    fn tangent(&self, position: f64) -> Vector
+    {
+        let heading = self.heading(position);
+        Vector::new(heading.cos(), heading.sin())
+    }
+
+    fn heading(&self, position: f64) -> f64
+    {
+        self.angle * position
+    }
+
+    // Turning without moving
+    fn curvature(&self, _position: f64) -> f64
+    {
+        signum(self.angle) * f64::INFINITY
+    }
+
+    fn heading_rate(&self, _position: f64) -> f64
+    {
+        signum(self.angle) / self.radius
+    }
+
+    // All of it is in the same place
+    fn project(&self, _point: &Vector) -> f64
+    {
+        0.0
+    }
+
+    fn spec(&self) -> Option<SegmentSpec>
+    {
+        Some(SegmentSpec::Rotate{angle: self.angle, radius: self.radius})
+    }
+}
+
+// Drives the given segment backwards. The heading
+// changes as it would driving forward, but the robot
+// moves the opposite way, so the path is the segment
+// mirrored through its start.
+pub struct ReverseSegment
+{
+    segment: Box<dyn PathSegment>,
+}
+
+impl ReverseSegment {
+    pub fn new(segment: Box<dyn PathSegment>) -> ReverseSegment
+    {
+        ReverseSegment{segment}
+    }
+}
+
+impl PathSegment for ReverseSegment
+{
+    fn length(&self) -> f64
+    {
+        self.segment.length()
+    }
+
+    fn extent(&self) -> f64
+    {
+        self.segment.extent()
+    }
+
+    fn at(&self, position: f64) -> (Vector, Rotation)
+    {
+        let (pos, rot) = self.segment.at(position);
+        (-pos, rot)
+    }
+
+    fn tangent(&self, position: f64) -> Vector
+    {
+        self.segment.tangent(position)
+    }
+
+    fn heading(&self, position: f64) -> f64
+    {
+        self.segment.heading(position)
+    }
+
+    fn curvature(&self, position: f64) -> f64
+    {
+        self.segment.curvature(position)
+    }
+
+    fn heading_rate(&self, position: f64) -> f64
+    {
+        self.segment.heading_rate(position)
+    }
+
+    fn project(&self, point: &Vector) -> f64
+    {
+        self.segment.project(&-point)
+    }
+
+    fn spec(&self) -> Option<SegmentSpec>
+    {
+        Some(SegmentSpec::Reverse{segment: Box::new(self.segment.spec()?)})
+    }
+
+    fn runs(&self) -> Vec<(f64, Direction)>
+    {
+        self.segment.runs().into_iter().map(|(start, direction)| (start, direction.reversed())).collect()
+    }
+
+    fn spins(&self) -> Vec<(f64, f64)>
+    {
+        self.segment.spins()
+    }
 }
 
 struct CompoundPathSegment
 {
     segment: Box<dyn PathSegment>,
     // where this segment starts, concatenated
-    // to the previous one
+    // to the previous one, relative to the
+    // start of the path
     pos: Vector,
     // the rotation we start off
     rot: Rotation,
-    // The end in relative units.
-    relative_start: f64,
-    // The length in relative units
-    relative_length: f64
+    // the same, but not wrapped
+    heading: f64,
+    // How far along the path the segment starts,
+    // in the units of extent.
+    start: f64,
 }
 
 impl fmt::Debug for CompoundPathSegment {
@@ -104,86 +563,383 @@ impl fmt::Debug for CompoundPathSegment {
         f.debug_struct("CompoundPathSegment")
          .field("pos", &(self.pos[0], self.pos[1]))
          .field("rot", &self.rot.angle())
-         .field("relative_start", &self.relative_start)
-         .field("relative_length", &self.relative_length)
+         .field("start", &self.start)
+         .field("extent", &self.segment.extent())
          .finish()
     }
 }
+
+// A path made up of segments, each one
+// continuing where the previous one ends.
+// The path begins at its start pose, which
+// defaults to the origin facing along the
+// x-axis. Compound paths can be nested as
+// segments of other paths.
 #[derive(Debug)]
 pub struct CompoundPath
 {
-    segments: Vec<CompoundPathSegment>
+    start: Pose,
+    segments: Vec<CompoundPathSegment>,
+    // where the next segment goes, relative
+    // to the start of the path
+    end_pos: Vector,
+    end_rot: Rotation,
+    end_heading: f64,
+    length: f64,
+    extent: f64,
+}
+
+impl Default for CompoundPath {
+    fn default() -> Self {
+        CompoundPath::new()
+    }
 }
 
 impl CompoundPath {
 
     pub fn new() -> CompoundPath
     {
-        CompoundPath{segments: Vec::new()}
+        CompoundPath::with_start(Pose::origin())
     }
 
-    fn _length(&self) -> f64
+    pub fn with_start(start: Pose) -> CompoundPath
     {
-        self.segments.iter().fold(0.0, |acc, x| acc + x.segment.length())
+        CompoundPath{
+            start,
+            segments: Vec::new(),
+            end_pos: Vector::new(0.0, 0.0),
+            end_rot: Rotation::new(0.0),
+            end_heading: 0.0,
+            length: 0.0,
+            extent: 0.0,
+        }
     }
 
+    pub fn start(&self) -> Pose
+    {
+        self.start
+    }
 
-    fn push(&mut self, segment: Box<dyn PathSegment>)
+    // Where the last segment ends
+    pub fn end(&self) -> Pose
     {
-        self.segments.push(CompoundPathSegment{
-            segment: segment,
-            relative_length: 0.0,
-            relative_start: 0.0,
-            pos: Vector::new(0.0, 0.0),
-            rot: Rotation::new(0.0),
-        });
-        let mut pos = Vector::new(0.0, 0.0);
-        let mut rot = Rotation::new(0.0);
-        let mut relative_start = 0.0;
-        let total_length = self._length();
+        Pose{
+            position: self.start.rotation.transform_vector(&self.end_pos) + self.start.position,
+            rotation: Rotation::new(self.start.heading() + self.end_rot.angle()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.segments.is_empty()
+    }
 
-        for segment in self.segments.iter_mut()
+    // Where the segments start, in relative
+    // units along the whole path
+    pub fn boundaries(&self) -> Vec<f64>
+    {
+        self.segments.iter().map(|segment| self.relative(segment.start)).collect()
+    }
+
+    fn relative(&self, extent: f64) -> f64
+    {
+        if self.extent > 0.0 {
+            extent / self.extent
+        }
+        else
         {
-            segment.relative_length = segment.segment.length() / total_length;
-            segment.relative_start = relative_start;
-            relative_start += segment.relative_length;
+            0.0
+        }
+    }
 
-            segment.pos = pos;
-            segment.rot = rot;
-            let (rpos, rrot) = segment.segment.at(1.0);
-            // our new pos for the next segment is
-            // placed at the end of the current one,
-            // properly rotated
-            pos += rot.transform_vector(&rpos);
-            rot = Rotation::new(rot.angle() + rrot.angle());
+    // Builds a path from the given segments, rejecting
+    // any the robot couldn't drive, see try_push.
+    pub fn try_from_segments(start: Pose, segments: Vec<Box<dyn PathSegment>>) -> Result<CompoundPath, PathError>
+    {
+        let mut path = CompoundPath::with_start(start);
+        for segment in segments {
+            path.try_push(segment)?;
         }
+        Ok(path)
+    }
+
+    // Like push, but rejects segments that don't
+    // extend, and those where length, extent or
+    // end pose aren't finite.
+    pub fn try_push(&mut self, segment: Box<dyn PathSegment>) -> Result<&mut CompoundPath, PathError>
+    {
+        let extent = segment.extent();
+        let (pos, rot) = segment.at(1.0);
+        let finite = segment.length().is_finite() && extent.is_finite()
+            && pos[0].is_finite() && pos[1].is_finite() && rot.angle().is_finite();
+        if !(finite && extent > 0.0) {
+            return Err(PathError::InvalidSegment{index: self.segments.len()});
+        }
+        Ok(self.push(segment))
+    }
+
+    // Like at, but instead of clamping it fails on
+    // empty paths and positions outside 0.0..1.0.
+    pub fn try_at(&self, position: f64) -> Result<(Vector, Rotation), PathError>
+    {
+        if self.segments.is_empty() {
+            return Err(PathError::Empty);
+        }
+        if position.is_nan() {
+            return Err(PathError::NotANumber);
+        }
+        if !(0.0..=1.0).contains(&position) {
+            return Err(PathError::OutOfRange(position));
+        }
+        Ok(self.at(position))
+    }
+
+    // Where the robot is relative to the path. A hint,
+    // usually the position found last time, limits the
+    // search to the part of the path within
+    // PROJECTION_WINDOW of it, so the robot doesn't skip
+    // ahead where the path crosses itself. None for an
+    // empty path.
+    pub fn project_pose(&self, pose: &Pose, hint: Option<f64>) -> Option<Projection>
+    {
+        match hint {
+            Some(hint) if self.extent > 0.0 => {
+                let window = PROJECTION_WINDOW / self.extent;
+                self.project_pose_within(pose, hint - window, hint + window)
+            },
+            _ => self.project_pose_within(pose, 0.0, 1.0),
+        }
+    }
+
+    // Like project_pose, only considering the part of
+    // the path between the two relative positions.
+    pub fn project_pose_within(&self, pose: &Pose, from: f64, to: f64) -> Option<Projection>
+    {
+        let (position, _) = self.closest(&pose.position, (from * self.extent, to * self.extent))?;
+        let (point, _) = self.at(position);
+        let tangent = self.tangent(position);
+        let offset = pose.position - point;
+        Some(Projection{
+            position,
+            point,
+            cross_track: tangent[0] * offset[1] - tangent[1] * offset[0],
+            heading_error: (pose.rotation * Rotation::new(self.heading(position)).inverse()).angle(),
+        })
+    }
+
+    // The relative position and distance of the
+    // closest point within the range of extent.
+    fn closest(&self, point: &Vector, range: (f64, f64)) -> Option<(f64, f64)>
+    {
+        let local = self.start.rotation.inverse_transform_vector(&(point - self.start.position));
+        let mut best: Option<(f64, f64)> = None;
+        for segment in self.segments.iter() {
+            let extent = segment.segment.extent();
+            if segment.start + extent < range.0 || segment.start > range.1 {
+                continue;
+            }
+            // only the part of the segment inside the range
+            let (low, high) = if extent > 0.0 {
+                (((range.0 - segment.start) / extent).max(0.0), ((range.1 - segment.start) / extent).min(1.0))
+            }
+            else
+            {
+                (0.0, 1.0)
+            };
+            let inside = segment.rot.inverse_transform_vector(&(local - segment.pos));
+            let position = segment.segment.project(&inside).max(low).min(high);
+            let distance = (segment.segment.at(position).0 - inside).norm();
+            if best.map_or(true, |(_, closest)| distance < closest) {
+                best = Some((self.relative(segment.start + position * extent), distance));
+            }
+        }
+        best
+    }
+
+    // The last segment starting at or before
+    // the given point along the path.
+    fn segment_index(&self, target: f64) -> usize
+    {
+        match self.segments.binary_search_by(
+            |segment| segment.start.partial_cmp(&target).unwrap_or(Ordering::Less))
+        {
+            Ok(index) => index,
+            Err(0) => 0,
+            Err(index) => index - 1
+        }
+    }
+
+    // The segment at the given position, and the
+    // position inside of it, following the policy
+    // of at. None for an empty path.
+    fn locate(&self, position: f64) -> Option<(&CompoundPathSegment, f64)>
+    {
+        if self.segments.is_empty() {
+            return None;
+        }
+        let position = if position.is_nan() { 0.0 } else { position.clamp(0.0, 1.0) };
+        let target = position * self.extent;
+        let segment = &self.segments[self.segment_index(target)];
+        // adjust the position relative to the
+        // start and extent of the segment
+        let extent = segment.segment.extent();
+        let position = if extent > 0.0 {
+            ((target - segment.start) / extent).clamp(0.0, 1.0)
+        }
+        else
+        {
+            1.0
+        };
+        Some((segment, position))
+    }
+
+    // Appends the segment at the end of the path. Only
+    // the new segment is placed, the others stay as
+    // they are.
+    pub fn push(&mut self, segment: Box<dyn PathSegment>) -> &mut CompoundPath
+    {
+        let (rpos, rrot) = segment.at(1.0);
+        let (length, extent) = (segment.length(), segment.extent());
+        let heading = segment.heading(1.0);
+        self.segments.push(CompoundPathSegment{
+            segment,
+            pos: self.end_pos,
+            rot: self.end_rot,
+            heading: self.end_heading,
+            start: self.extent,
+        });
+        self.end_heading += heading;
+        // the next segment is placed at the
+        // end of this one, properly rotated
+        self.end_pos += self.end_rot.transform_vector(&rpos);
+        self.end_rot = Rotation::new(self.end_rot.angle() + rrot.angle());
+        self.length += length;
+        self.extent += extent;
+        self
+    }
+
+    pub fn extend<I>(&mut self, segments: I) -> &mut CompoundPath
+    where I: IntoIterator<Item = Box<dyn PathSegment>>
+    {
+        for segment in segments {
+            self.push(segment);
+        }
+        self
+    }
+
+    // Continues with the segments of the given path.
+    // Its start pose is ignored, the segments pick up
+    // where this path ends. To keep the path as one
+    // segment, push it instead.
+    pub fn append_path(&mut self, path: CompoundPath) -> &mut CompoundPath
+    {
+        self.extend(path.segments.into_iter().map(|segment| segment.segment))
     }
 }
 
 impl PathSegment for CompoundPath
 {
+    // Nested inside another path, the start pose
+    // is relative to the end of the previous segment.
+    //
+    // Positions are clamped to 0.0..1.0, and NaN is
+    // taken as 0.0. An empty path stays at its
+    // start pose. Segments without extent are
+    // passed over, they are evaluated at their end.
+    // Use try_at to have these cases reported.
     fn at(&self, position: f64) -> (Vector, Rotation)
     {
-        let index = match self.segments.binary_search_by(
-            |segment| segment.relative_start.partial_cmp(&position).expect("Nan"))
-        {
-            Ok(index) => index,
-            Err(index) => index - 1
+        let (segment, position) = match self.locate(position) {
+            Some(located) => located,
+            None => return (self.start.position, self.start.rotation),
         };
-        let segment = &self.segments[index];
-        // adjust the position relative to the
-        // start and length
-        let position = (position - segment.relative_start) / segment.relative_length;
         let (rpos, rrot) = segment.segment.at(position);
 
         let pos = segment.rot.transform_vector(&rpos) + segment.pos;
-        let rot = Rotation::new(segment.rot.angle() + rrot.angle());
-        (pos, rot)
+        let rot = segment.rot.angle() + rrot.angle();
+        let pos = self.start.rotation.transform_vector(&pos) + self.start.position;
+        (pos, Rotation::new(self.start.heading() + rot))
+    }
+
+    // Where segments meet, the one
+    // starting there is asked.
+    fn tangent(&self, position: f64) -> Vector
+    {
+        let tangent = match self.locate(position) {
+            Some((segment, position)) => segment.rot.transform_vector(&segment.segment.tangent(position)),
+            None => Vector::new(1.0, 0.0),
+        };
+        self.start.rotation.transform_vector(&tangent)
+    }
+
+    fn heading(&self, position: f64) -> f64
+    {
+        self.start.heading() + self.locate(position)
+            .map_or(0.0, |(segment, position)| segment.heading + segment.segment.heading(position))
+    }
+
+    fn curvature(&self, position: f64) -> f64
+    {
+        self.locate(position).map_or(0.0, |(segment, position)| segment.segment.curvature(position))
+    }
+
+    fn heading_rate(&self, position: f64) -> f64
+    {
+        self.locate(position).map_or(0.0, |(segment, position)| segment.segment.heading_rate(position))
+    }
+
+    fn project(&self, point: &Vector) -> f64
+    {
+        self.closest(point, (0.0, self.extent)).map_or(0.0, |(position, _)| position)
     }
 
     fn length(&self) -> f64
     {
-        self._length()
+        self.length
+    }
+
+    fn extent(&self) -> f64
+    {
+        self.extent
+    }
+
+    fn spec(&self) -> Option<SegmentSpec>
+    {
+        let segments = self.segments.iter().map(|segment| segment.segment.spec()).collect::<Option<Vec<_>>>()?;
+        Some(SegmentSpec::Compound{segments})
+    }
+
+    fn runs(&self) -> Vec<(f64, Direction)>
+    {
+        let mut runs: Vec<(f64, Direction)> = Vec::new();
+        for segment in self.segments.iter() {
+            let start = self.relative(segment.start);
+            let length = self.relative(segment.segment.extent());
+            for (run_start, direction) in segment.segment.runs() {
+                // consecutive runs in the same
+                // direction make up one run
+                if runs.last().map_or(true, |(_, last)| *last != direction) {
+                    runs.push((start + run_start * length, direction));
+                }
+            }
+        }
+        if runs.is_empty() {
+            runs.push((0.0, Direction::Forward));
+        }
+        runs
+    }
+
+    fn spins(&self) -> Vec<(f64, f64)>
+    {
+        let mut spins = Vec::new();
+        for segment in self.segments.iter() {
+            let start = self.relative(segment.start);
+            let length = self.relative(segment.segment.extent());
+            for (spin_start, spin_end) in segment.segment.spins() {
+                spins.push((start + spin_start * length, start + spin_end * length));
+            }
+        }
+        spins
     }
 }
 
@@ -207,6 +963,11 @@ pub struct Ramp
 impl Ramp
 {
 
+    pub fn new(length: f64, max_velocity: f64, max_acceleration: f64) -> Ramp
+    {
+        Ramp{length, max_velocity, max_acceleration}
+    }
+
     fn segment_duration(&self)-> (f64, f64)
     {
         let mut full_speed_time = 0.0;
@@ -243,13 +1004,13 @@ impl Ramp
         (ramp_time, full_speed_time)
     }
 
-    fn total_duration(&self) -> Duration
+    pub fn total_duration(&self) -> Duration
     {
         let (ramp_time, full_speed_time) = self.segment_duration();
         Duration::from_secs_f64(ramp_time + full_speed_time)
     }
 
-    fn position_at_duration(&self, when: Duration) -> f64
+    pub fn position_at_duration(&self, when: Duration) -> f64
     {
         let mut when = when.as_secs_f64();
         let (ramp_time, full_speed_time) = self.segment_duration();
@@ -282,6 +1043,163 @@ impl Ramp
             }
         }
     }
+
+    pub fn velocity_at_duration(&self, when: Duration) -> f64
+    {
+        // compared as Durations, which are rounded to
+        // nanoseconds, so the end is really standing still
+        if when >= self.total_duration() {
+            return 0.0;
+        }
+        let when = when.as_secs_f64();
+        let (ramp_time, full_speed_time) = self.segment_duration();
+        let first_ramp_time = ramp_time / 2.0;
+        let top_velocity = self.max_acceleration * first_ramp_time;
+        match when {
+            when if when <= first_ramp_time => self.max_acceleration * when,
+            when if when <= first_ramp_time + full_speed_time => top_velocity,
+            when => {
+                let decelerating = when - first_ramp_time - full_speed_time;
+                (top_velocity - self.max_acceleration * decelerating).max(0.0)
+            }
+        }
+    }
+}
+
+// A velocity profile like the Ramp, but limiting the
+// jerk as well. The acceleration doesn't jump between
+// its extremes, it builds up and decays at max_jerk,
+// which is easier on the chassis and keeps the wheels
+// from slipping.
+//
+// Speeding up takes three phases, increasing the
+// acceleration, keeping it, and reducing it again,
+// then the top speed is held, and slowing down
+// mirrors speeding up. Short distances don't reach
+// max_velocity, or not even max_acceleration, then
+// the phases holding those are left out.
+//
+// Jerk is given in cm/s^3
+pub struct SCurve
+{
+    length: f64,
+    max_jerk: f64,
+    // how long each of the jerk phases lasts,
+    // how long the acceleration is held, and
+    // how long the top speed
+    jerk_time: f64,
+    acceleration_time: f64,
+    cruise_time: f64,
+}
+
+impl SCurve
+{
+
+    pub fn new(length: f64, max_velocity: f64, max_acceleration: f64, max_jerk: f64) -> SCurve
+    {
+        let (a, j) = (max_acceleration, max_jerk);
+        // The distance covered getting up to the given
+        // speed and down again. Below a^2 / j the
+        // acceleration doesn't reach a in the meantime.
+        let distance = |v: f64| if v * j >= a * a {
+            v * (v / a + a / j)
+        }
+        else
+        {
+            2.0 * v * (v / j).sqrt()
+        };
+        let velocity = if distance(max_velocity) <= length {
+            max_velocity
+        }
+        else
+        {
+            // solving distance(v) = length, first
+            // assuming we still reach a
+            let v = a / 2.0 * ((a * a / (j * j) + 4.0 * length / a).sqrt() - a / j);
+            if v * j >= a * a {
+                v
+            }
+            else
+            {
+                (length * length * j / 4.0).cbrt()
+            }
+        };
+        if velocity <= 0.0 {
+            return SCurve{length, max_jerk, jerk_time: 0.0, acceleration_time: 0.0, cruise_time: 0.0};
+        }
+        let peak_acceleration = a.min((velocity * j).sqrt());
+        let jerk_time = peak_acceleration / j;
+        SCurve{
+            length,
+            max_jerk,
+            jerk_time,
+            acceleration_time: (velocity / peak_acceleration - jerk_time).max(0.0),
+            cruise_time: ((length - distance(velocity)) / velocity).max(0.0),
+        }
+    }
+
+    // The duration and jerk of each phase
+    fn phases(&self) -> [(f64, f64); 7]
+    {
+        let (jerk_time, jerk) = (self.jerk_time, self.max_jerk);
+        [
+            (jerk_time, jerk),
+            (self.acceleration_time, 0.0),
+            (jerk_time, -jerk),
+            (self.cruise_time, 0.0),
+            (jerk_time, -jerk),
+            (self.acceleration_time, 0.0),
+            (jerk_time, jerk),
+        ]
+    }
+
+    // Position and velocity after the given
+    // number of seconds, integrating the jerk
+    // phase by phase.
+    fn state_at(&self, when: f64) -> (f64, f64)
+    {
+        let (mut position, mut velocity, mut acceleration) = (0.0, 0.0, 0.0);
+        let mut when = when;
+        for (duration, jerk) in self.phases().iter() {
+            let t = duration.min(when);
+            position += velocity * t + acceleration * t * t / 2.0 + jerk * t * t * t / 6.0;
+            velocity += acceleration * t + jerk * t * t / 2.0;
+            acceleration += jerk * t;
+            when -= t;
+            if when <= 0.0 {
+                break;
+            }
+        }
+        (position, velocity)
+    }
+
+    // In seconds, without the rounding
+    // to nanoseconds of a Duration
+    fn total_seconds(&self) -> f64
+    {
+        self.phases().iter().map(|(duration, _)| duration).sum()
+    }
+
+    pub fn total_duration(&self) -> Duration
+    {
+        Duration::from_secs_f64(self.total_seconds())
+    }
+
+    pub fn position_at_duration(&self, when: Duration) -> f64
+    {
+        if when >= self.total_duration() {
+            return self.length;
+        }
+        self.state_at(when.as_secs_f64()).0.min(self.length)
+    }
+
+    pub fn velocity_at_duration(&self, when: Duration) -> f64
+    {
+        if when >= self.total_duration() {
+            return 0.0;
+        }
+        self.state_at(when.as_secs_f64()).1.max(0.0)
+    }
 }
 
 #[cfg(test)]
@@ -367,6 +1285,313 @@ mod tests {
         assert_eq!(Rotation::new(PI / 2.0), rot);
     }
 
+    #[test]
+    fn rotate_segment() {
+        let segment = RotateSegment::new(-PI / 2.0, 10.0);
+        assert_eq!(segment.length(), 0.0);
+        assert_eq!(segment.extent(), PI * 5.0);
+        let (pos, rot) = segment.at(0.5);
+        assert_eq!(pos, Vector::new(0.0, 0.0));
+        assert_eq!(Rotation::new(-PI / 4.0), rot);
+    }
+
+    #[test]
+    fn compound_path_with_rotation() {
+        // Drive 10, turn left on the spot, drive 10
+        // again. The turn takes up as much of the path
+        // as the wheels travel while turning.
+        let radius = 20.0 / PI;
+        let mut compound_path = CompoundPath::new();
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        compound_path.push(Box::new(RotateSegment::new(PI / 2.0, radius)));
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        assert_eq!(compound_path.length(), 20.0);
+        assert!((compound_path.extent() - 30.0).abs() < 0.0001);
+        assert_eq!(compound_path.boundaries().len(), 3);
+
+        let (pos, rot) = compound_path.at(0.5);
+        assert!(equal_eps(&Vector::new(10.0, 0.0), &pos, 0.0001));
+        assert!((rot.angle() - PI / 4.0).abs() < 0.0001);
+        let (pos, rot) = compound_path.at(1.0);
+        assert!(equal_eps(&Vector::new(10.0, 10.0), &pos, 0.0001));
+        assert!((rot.angle() - PI / 2.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn reverse_segment() {
+        let segment = ReverseSegment::new(Box::new(CircleSegment::new(4.0, PI / 2.0)));
+        assert_eq!(segment.length(), 2.0 * PI);
+        let (pos, rot) = segment.at(1.0);
+        assert!(equal_eps(&pos, &Vector::new(-4.0, -4.0), 0.0001));
+        assert_eq!(Rotation::new(PI / 2.0), rot);
+        assert_eq!(segment.runs(), vec![(0.0, Direction::Reverse)]);
+        let twice = ReverseSegment::new(Box::new(segment));
+        assert_eq!(twice.runs(), vec![(0.0, Direction::Forward)]);
+    }
+
+    #[test]
+    fn compound_path_runs() {
+        // Into the parking bay: drive past it, back in
+        // and come out again.
+        let mut compound_path = CompoundPath::new();
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        compound_path.push(Box::new(ReverseSegment::new(Box::new(LinearSegment::new(20.0)))));
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        assert_eq!(compound_path.runs(), vec![
+            (0.0, Direction::Forward),
+            (0.4, Direction::Reverse),
+            (0.8, Direction::Forward),
+        ]);
+        let (pos, _) = compound_path.at(0.8);
+        assert!(equal_eps(&Vector::new(0.0, 0.0), &pos, 0.0001));
+        let (pos, _) = compound_path.at(0.6);
+        assert!(equal_eps(&Vector::new(10.0, 0.0), &pos, 0.0001));
+    }
+
+    #[test]
+    fn compound_path_spins() {
+        // a quarter turn with the wheels 5cm
+        // out travels 2.5 * PI cm
+        let mut inner = CompoundPath::new();
+        inner
+            .push(Box::new(RotateSegment::new(PI / 2.0, 5.0)))
+            .push(Box::new(LinearSegment::new(2.5 * PI)));
+        let mut compound_path = CompoundPath::new();
+        compound_path
+            .push(Box::new(LinearSegment::new(5.0 * PI)))
+            .push(Box::new(ReverseSegment::new(Box::new(inner))));
+        assert_eq!(LinearSegment::new(10.0).spins(), vec![]);
+        assert_eq!(compound_path.spins(), vec![(0.5, 0.75)]);
+    }
+
+    #[test]
+    fn compound_path_start_pose() {
+        let mut compound_path = CompoundPath::with_start(Pose::new(5.0, 5.0, PI / 2.0));
+        compound_path
+            .push(Box::new(LinearSegment::new(10.0)))
+            .push(Box::new(CircleSegment::new(4.0, -PI / 2.0)));
+        let (pos, rot) = compound_path.at(0.0);
+        assert!(equal_eps(&Vector::new(5.0, 5.0), &pos, 0.0001));
+        assert_eq!(Rotation::new(PI / 2.0), rot);
+        let (pos, _) = compound_path.at(1.0);
+        assert!(equal_eps(&Vector::new(9.0, 19.0), &pos, 0.0001));
+        let end = compound_path.end();
+        assert!(equal_eps(&Vector::new(9.0, 19.0), &end.position, 0.0001));
+        assert!(end.heading().abs() < 0.0001);
+    }
+
+    #[test]
+    fn compound_path_nesting_and_appending() {
+        let turn = || {
+            let mut path = CompoundPath::new();
+            path.extend(vec![
+                Box::new(LinearSegment::new(1.0)) as Box<dyn PathSegment>,
+                Box::new(CircleSegment::new(4.0, PI / 2.0)),
+            ]);
+            path
+        };
+        let mut nested = CompoundPath::new();
+        nested.push(Box::new(LinearSegment::new(10.0))).push(Box::new(turn()));
+        let mut appended = CompoundPath::new();
+        appended.push(Box::new(LinearSegment::new(10.0))).append_path(turn());
+
+        assert_eq!(nested.boundaries().len(), 2);
+        assert_eq!(appended.boundaries().len(), 3);
+        assert_eq!(nested.length(), appended.length());
+        for position in [0.0, 0.3, 0.6, 1.0].iter() {
+            let (pos, rot) = nested.at(*position);
+            let (pos2, rot2) = appended.at(*position);
+            assert!(equal_eps(&pos, &pos2, 0.0001));
+            assert!((rot.angle() - rot2.angle()).abs() < 0.0001);
+        }
+        let (pos, _) = nested.at(1.0);
+        assert!(equal_eps(&Vector::new(15.0, 4.0), &pos, 0.0001));
+    }
+
+    #[test]
+    fn compound_path_edges() {
+        let empty = CompoundPath::with_start(Pose::new(1.0, 2.0, 0.5));
+        assert_eq!(empty.at(0.5), (Vector::new(1.0, 2.0), Rotation::new(0.5)));
+        assert_eq!(empty.try_at(0.5), Err(PathError::Empty));
+
+        let mut compound_path = CompoundPath::new();
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        assert_eq!(compound_path.at(-1.0), compound_path.at(0.0));
+        assert_eq!(compound_path.at(f64::NAN), compound_path.at(0.0));
+        assert_eq!(compound_path.at(2.0), compound_path.at(1.0));
+        assert!(equal_eps(&Vector::new(20.0, 0.0), &compound_path.at(f64::INFINITY).0, 0.0001));
+        assert_eq!(compound_path.try_at(1.5), Err(PathError::OutOfRange(1.5)));
+        assert_eq!(compound_path.try_at(-0.1), Err(PathError::OutOfRange(-0.1)));
+        assert_eq!(compound_path.try_at(f64::NAN), Err(PathError::NotANumber));
+        assert_eq!(compound_path.try_at(0.25), Ok(compound_path.at(0.25)));
+    }
+
+    #[test]
+    fn compound_path_rejects_bad_segments() {
+        let mut compound_path = CompoundPath::new();
+        assert!(compound_path.try_push(Box::new(LinearSegment::new(10.0))).is_ok());
+        assert_eq!(compound_path.try_push(Box::new(CircleSegment::new(0.0, PI))).err(),
+                   Some(PathError::InvalidSegment{index: 1}));
+        assert!(compound_path.try_push(Box::new(LinearSegment::new(f64::NAN))).is_err());
+        assert!(compound_path.try_push(Box::new(LinearSegment::new(f64::INFINITY))).is_err());
+        assert_eq!(compound_path.boundaries().len(), 1);
+
+        let segments: Vec<Box<dyn PathSegment>> = vec![
+            Box::new(LinearSegment::new(10.0)),
+            Box::new(LinearSegment::new(0.0)),
+        ];
+        assert_eq!(CompoundPath::try_from_segments(Pose::origin(), segments).err(),
+                   Some(PathError::InvalidSegment{index: 1}));
+
+        // pushed unchecked, zero length segments
+        // are passed over
+        compound_path.push(Box::new(LinearSegment::new(0.0)));
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        assert!(equal_eps(&Vector::new(10.0, 0.0), &compound_path.at(0.5).0, 0.0001));
+        assert!(equal_eps(&Vector::new(20.0, 0.0), &compound_path.at(1.0).0, 0.0001));
+    }
+
+    // Only knows its geometry, so
+    // curvature is estimated.
+    struct Estimated(CircleSegment);
+
+    impl PathSegment for Estimated
+    {
+        fn length(&self) -> f64
+        {
+            self.0.length()
+        }
+
+        fn at(&self, position: f64) -> (Vector, Rotation)
+        {
+            self.0.at(position)
+        }
+    }
+
+    #[test]
+    fn segment_curvature_and_tangent() {
+        let circle = CircleSegment::new(4.0, -PI);
+        assert_eq!(circle.curvature(0.3), -0.25);
+        assert_eq!(circle.heading(1.0), -PI);
+        assert!(equal_eps(&circle.tangent(0.5), &Vector::new(0.0, -1.0), 1e-12));
+        assert_eq!(LinearSegment::new(3.0).curvature(0.5), 0.0);
+
+        let estimated = Estimated(CircleSegment::new(4.0, PI / 2.0));
+        for position in [0.0, 0.5, 1.0].iter() {
+            assert!((estimated.curvature(*position) - 0.25).abs() < 1e-6);
+        }
+        assert!(equal_eps(&estimated.tangent(1.0), &Vector::new(0.0, 1.0), 1e-12));
+
+        let spin = RotateSegment::new(-PI, 10.0);
+        assert_eq!(spin.curvature(0.5), f64::NEG_INFINITY);
+        assert_eq!(spin.heading_rate(0.5), -0.1);
+        let back = ReverseSegment::new(Box::new(CircleSegment::new(2.0, PI)));
+        assert_eq!(back.curvature(0.5), 0.5);
+    }
+
+    #[test]
+    fn compound_path_curvature() {
+        let mut compound_path = CompoundPath::with_start(Pose::new(0.0, 0.0, PI / 2.0));
+        compound_path
+            .push(Box::new(LinearSegment::new(10.0)))
+            .push(Box::new(CircleSegment::new(5.0, 2.0 * PI)))
+            .push(Box::new(CircleSegment::new(10.0, -PI)));
+        let total = compound_path.length();
+        let at = |distance: f64| distance / total;
+        assert_eq!(compound_path.curvature(at(5.0)), 0.0);
+        assert_eq!(compound_path.curvature(at(11.0)), 0.2);
+        assert_eq!(compound_path.curvature(at(20.0)), 0.2);
+        assert_eq!(compound_path.curvature(at(50.0)), -0.1);
+        // one full turn left, then half a turn right
+        assert!((compound_path.heading(at(10.0 + 10.0 * PI)) - 2.5 * PI).abs() < 1e-9);
+        assert!((compound_path.heading(1.0) - 1.5 * PI).abs() < 1e-9);
+        assert!(equal_eps(&compound_path.tangent(at(5.0)), &Vector::new(0.0, 1.0), 1e-9));
+        assert!(equal_eps(&compound_path.tangent(1.0), &Vector::new(0.0, -1.0), 1e-9));
+        assert_eq!(CompoundPath::new().curvature(0.5), 0.0);
+    }
+
+    #[test]
+    fn segment_projection() {
+        let line = LinearSegment::new(10.0);
+        assert_eq!(line.project(&Vector::new(5.0, 3.0)), 0.5);
+        assert_eq!(line.project(&Vector::new(-5.0, 3.0)), 0.0);
+        assert_eq!(line.project(&Vector::new(15.0, -3.0)), 1.0);
+
+        let circle = CircleSegment::new(4.0, PI / 2.0);
+        let halfway = Vector::new(0.0, 4.0) + Vector::new(1.0, -1.0) * 5.0 / 2f64.sqrt();
+        assert!((circle.project(&halfway) - 0.5).abs() < 1e-12);
+        assert_eq!(circle.project(&Vector::new(6.0, 4.0)), 1.0);
+        assert_eq!(circle.project(&Vector::new(-3.0, 0.0)), 0.0);
+        let right = CircleSegment::new(4.0, -PI / 2.0);
+        let halfway = Vector::new(halfway[0], -halfway[1]);
+        assert!((right.project(&halfway) - 0.5).abs() < 1e-12);
+
+        let estimated = Estimated(CircleSegment::new(4.0, PI / 2.0));
+        for point in [Vector::new(1.0, 1.0), Vector::new(3.0, 5.0), Vector::new(-1.0, -1.0)].iter() {
+            assert!((estimated.project(point) - circle.project(point)).abs() < 1e-6);
+        }
+
+        let back = ReverseSegment::new(Box::new(LinearSegment::new(10.0)));
+        assert_eq!(back.project(&Vector::new(-2.5, 1.0)), 0.25);
+    }
+
+    #[test]
+    fn pose_projection() {
+        let mut compound_path = CompoundPath::with_start(Pose::new(10.0, 0.0, PI / 2.0));
+        compound_path.push(Box::new(LinearSegment::new(10.0)));
+        let projection = compound_path.project_pose(&Pose::new(8.0, 5.0, PI / 2.0 + 0.1), None).unwrap();
+        assert!((projection.position - 0.5).abs() < 1e-12);
+        assert!(equal_eps(&projection.point, &Vector::new(10.0, 5.0), 1e-12));
+        // left of the path is west, when heading north
+        assert!((projection.cross_track - 2.0).abs() < 1e-12);
+        assert!((projection.heading_error - 0.1).abs() < 1e-12);
+        assert_eq!(CompoundPath::new().project_pose(&Pose::origin(), None), None);
+    }
+
+    #[test]
+    fn pose_projection_where_the_path_crosses_itself() {
+        // Out east, three quarters of a circle left, and
+        // down south, crossing the first line at (80, 0).
+        let mut compound_path = CompoundPath::new();
+        compound_path
+            .push(Box::new(LinearSegment::new(100.0)))
+            .push(Box::new(CircleSegment::new(20.0, 1.5 * PI)))
+            .push(Box::new(LinearSegment::new(100.0)));
+        let extent = compound_path.extent();
+        let pose = Pose::new(80.3, 0.2, -PI / 2.0);
+
+        let projection = compound_path.project_pose(&pose, None).unwrap();
+        assert!((projection.position - 80.3 / extent).abs() < 1e-9);
+        assert!((projection.cross_track - 0.2).abs() < 1e-9);
+        assert!((projection.heading_error + PI / 2.0).abs() < 1e-9);
+
+        let third = 100.0 + 30.0 * PI;
+        let projection = compound_path.project_pose(&pose, Some((third + 5.0) / extent)).unwrap();
+        assert!((projection.position - (third + 19.8) / extent).abs() < 1e-9);
+        assert!((projection.cross_track - 0.3).abs() < 1e-9);
+        assert!(projection.heading_error.abs() < 1e-9);
+    }
+
+    #[test]
+    fn pose_projection_within_a_range() {
+        // there and back again along the same line
+        let mut compound_path = CompoundPath::new();
+        compound_path
+            .push(Box::new(LinearSegment::new(40.0)))
+            .push(Box::new(ReverseSegment::new(Box::new(LinearSegment::new(40.0)))));
+        let pose = Pose::new(30.0, 1.0, 0.0);
+        let projection = compound_path.project_pose(&pose, None).unwrap();
+        assert!((projection.position - 0.375).abs() < 1e-12);
+        let projection = compound_path.project_pose_within(&pose, 0.5, 1.0).unwrap();
+        assert!((projection.position - 0.625).abs() < 1e-12);
+        assert!((projection.cross_track - 1.0).abs() < 1e-12);
+        // the range limits the position, too
+        let projection = compound_path.project_pose_within(&pose, 0.0, 0.25).unwrap();
+        assert!((projection.position - 0.25).abs() < 1e-12);
+    }
+
     #[test]
     fn ramp_duration()
     {
@@ -385,7 +1610,7 @@ mod tests {
         // This leaves us with 500.0 - 90.0 -> 410.0cm spent
         // at 30.0 cm/s, for 13.6666 seconds.
         // So total should 19.6666 seconds
-        let expectation = 19.666666666;
+        let expectation = 6.0 + 410.0 / 30.0;
         let ramp = Ramp{ length, max_velocity: speed, max_acceleration: acceleration };
         assert_eq!(Duration::from_secs_f64(expectation), ramp.total_duration());
     }
@@ -416,10 +1641,10 @@ mod tests {
         // The robot turns 3 rps with 10cm
         // wheel diameter. So a realistic speed
         // is 90cm/s. I take a conservative third there.
-        let speed = 30.0 as f64;
+        let speed = 30.0_f64;
         // if we want to reach full speed withn 3 seconds,
         // this means that we have 10cm/s^2 acceleration
-        let acceleration = 10.0 as f64;
+        let acceleration = 10.0_f64;
         let length = 50.0;
         // The area under the triangular acceleration
         // is equal to
@@ -472,6 +1697,17 @@ mod tests {
         assert_eq!(length / 2.0, ramp.position_at_duration(ramp.total_duration().mul_f64(0.5)));
     }
 
+    #[test]
+    fn ramp_velocity()
+    {
+        let ramp = Ramp::new(500.0, 30.0, 10.0);
+        assert_eq!(0.0, ramp.velocity_at_duration(Duration::from_secs(0)));
+        assert_eq!(15.0, ramp.velocity_at_duration(Duration::from_secs_f64(1.5)));
+        assert_eq!(30.0, ramp.velocity_at_duration(Duration::from_secs(10)));
+        assert!((ramp.velocity_at_duration(ramp.total_duration() - Duration::from_secs(1)) - 10.0).abs() < 0.0001);
+        assert_eq!(0.0, ramp.velocity_at_duration(ramp.total_duration()));
+    }
+
     #[test]
     fn ramp_position_shortly_before_end()
     {
@@ -482,4 +1718,79 @@ mod tests {
         let ramp = Ramp{ length, max_velocity: speed, max_acceleration: acceleration };
         assert_eq!(length - decl_size, ramp.position_at_duration(ramp.total_duration() - Duration::from_secs_f64(1.0)));
     }
+
+    #[test]
+    fn scurve_reaching_all_limits()
+    {
+        // Half a second to build up 10cm/s^2, 2.5s
+        // holding it, and another half second to get
+        // rid of it makes 30cm/s after 3.5s. Speeding
+        // up and slowing down covers 30 * 3.5 -> 105cm,
+        // leaving 395cm at full speed.
+        let scurve = SCurve::new(500.0, 30.0, 10.0, 20.0);
+        let expectation = 7.0 + 395.0 / 30.0;
+        assert!((scurve.total_duration().as_secs_f64() - expectation).abs() < 1e-6);
+        assert!((scurve.velocity_at_duration(Duration::from_secs_f64(0.5)) - 2.5).abs() < 1e-9);
+        assert!((scurve.position_at_duration(Duration::from_secs_f64(0.5)) - 20.0 / 6.0 * 0.125).abs() < 1e-9);
+        assert!((scurve.velocity_at_duration(Duration::from_secs(10)) - 30.0).abs() < 1e-9);
+        let half = scurve.total_duration().mul_f64(0.5);
+        assert!((scurve.position_at_duration(half) - 250.0).abs() < 1e-6);
+        assert_eq!(500.0, scurve.position_at_duration(scurve.total_duration()));
+        assert_eq!(0.0, scurve.velocity_at_duration(scurve.total_duration()));
+    }
+
+    #[test]
+    fn scurve_without_reaching_max_acceleration()
+    {
+        // with little jerk, reaching 30cm/s takes
+        // only sqrt(30)cm/s^2 of acceleration
+        let scurve = SCurve::new(500.0, 30.0, 10.0, 1.0);
+        let top = scurve.velocity_at_duration(scurve.total_duration().mul_f64(0.5));
+        assert!((top - 30.0).abs() < 1e-9);
+        let step = 0.001;
+        let mut previous = 0.0;
+        for index in 1..(scurve.total_duration().as_secs_f64() / step) as usize {
+            let velocity = scurve.velocity_at_duration(Duration::from_secs_f64(index as f64 * step));
+            assert!((velocity - previous).abs() <= 30f64.sqrt() * step + 1e-9);
+            previous = velocity;
+        }
+    }
+
+    #[test]
+    fn scurve_short_distances()
+    {
+        // Neither max_velocity nor max_acceleration are
+        // reached, the top speed then is (length^2 * jerk / 4)^(1/3)
+        // Halfway is taken in seconds, as a Duration
+        // it would be rounded to nanoseconds.
+        let scurve = SCurve::new(2.0, 30.0, 10.0, 20.0);
+        let (position, velocity) = scurve.state_at(scurve.total_seconds() / 2.0);
+        assert!((velocity - 20f64.cbrt()).abs() < 1e-9);
+        assert!((position - 1.0).abs() < 1e-9);
+        assert_eq!(2.0, scurve.position_at_duration(scurve.total_duration()));
+
+        // max_acceleration is reached, but not max_velocity
+        let scurve = SCurve::new(20.0, 30.0, 10.0, 100.0);
+        let (position, top) = scurve.state_at(scurve.total_seconds() / 2.0);
+        assert!(top < 30.0 && top > 10.0);
+        assert!((position - 10.0).abs() < 1e-9);
+
+        let nothing = SCurve::new(0.0, 30.0, 10.0, 20.0);
+        assert_eq!(Duration::from_secs(0), nothing.total_duration());
+        assert_eq!(0.0, nothing.position_at_duration(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn scurve_approaches_ramp()
+    {
+        // with hardly any jerk limit, it's a Ramp
+        for length in [5.0, 500.0].iter() {
+            let scurve = SCurve::new(*length, 30.0, 10.0, 1e9);
+            let ramp = Ramp::new(*length, 30.0, 10.0);
+            let difference = scurve.total_duration().as_secs_f64() - ramp.total_duration().as_secs_f64();
+            assert!(difference.abs() < 1e-6);
+            let half = ramp.total_duration().mul_f64(0.3);
+            assert!((scurve.position_at_duration(half) - ramp.position_at_duration(half)).abs() < 1e-4);
+        }
+    }
 }
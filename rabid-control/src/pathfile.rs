@@ -0,0 +1,406 @@
+// Storing compound paths in files.
+//
+// A CompoundPath only knows its segments as trait
+// objects, so it can't be serialized directly. Instead
+// every segment describes itself as a SegmentSpec, a
+// plain enum tagged with the segment type (see path),
+// which is what ends up in the file:
+//
+// {"segments": [
+//   {"type": "linear", "length": 10.0},
+//   {"type": "circle", "radius": 4.0, "arc": 1.5707963},
+//   {"type": "rotate", "angle": 3.1415926, "radius": 11.75},
+//   {"type": "reverse", "segment": {"type": "linear", "length": 5.0}}
+// ]}
+//
+// JSON, TOML and YAML are supported, as well as
+// path scripts, see pathscript. The format is
+// picked by the file extension.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::bezier::BezierSegment;
+use crate::clothoid::ClothoidSegment;
+use crate::pathscript;
+use crate::path::{PathSegment, PathError, Pose, CompoundPath, LinearSegment, CircleSegment, RotateSegment, ReverseSegment, Vector};
+pub use crate::path::SegmentSpec;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PathFile
+{
+    pub segments: Vec<SegmentSpec>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format
+{
+    Json,
+    Toml,
+    Yaml,
+    Script,
+}
+
+#[derive(Debug)]
+pub enum PathFileError
+{
+    Io(io::Error),
+    UnknownFormat,
+    Parse(String),
+    Serialize(String),
+    // The path holds segments without a spec
+    Unsupported,
+    // The segment is given by its indices, one
+    // per level of nesting.
+    Invalid{segment: Vec<usize>, reason: &'static str},
+}
+
+impl fmt::Display for PathFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathFileError::Io(error) => write!(f, "can't access path file: {}", error),
+            PathFileError::UnknownFormat => write!(f, "unknown path file format, use .json, .toml, .yaml or .path"),
+            PathFileError::Parse(error) => write!(f, "malformed path file: {}", error),
+            PathFileError::Serialize(error) => write!(f, "can't write path file: {}", error),
+            PathFileError::Unsupported => write!(f, "path contains segments path files can't describe"),
+            PathFileError::Invalid{segment, reason} => {
+                let segment: Vec<String> = segment.iter().map(|index| index.to_string()).collect();
+                write!(f, "segment {}: {}", segment.join("."), reason)
+            },
+        }
+    }
+}
+
+impl std::error::Error for PathFileError {}
+
+impl From<io::Error> for PathFileError {
+    fn from(error: io::Error) -> Self {
+        PathFileError::Io(error)
+    }
+}
+
+impl Format {
+
+    pub fn from_path(path: &Path) -> Result<Format, PathFileError>
+    {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Ok(Format::Json),
+            Some("toml") => Ok(Format::Toml),
+            Some("yaml") | Some("yml") => Ok(Format::Yaml),
+            Some("path") => Ok(Format::Script),
+            _ => Err(PathFileError::UnknownFormat),
+        }
+    }
+}
+
+impl SegmentSpec {
+
+    // Checks the segment can actually be driven. Errors
+    // carry the position of the offending segment.
+    pub fn validate(&self) -> Result<(), PathFileError>
+    {
+        self.validate_at(&mut Vec::new())
+    }
+
+    fn validate_at(&self, position: &mut Vec<usize>) -> Result<(), PathFileError>
+    {
+        let invalid = |reason| Err(PathFileError::Invalid{segment: position.clone(), reason});
+        match self {
+            SegmentSpec::Linear{length} => {
+                if !(length.is_finite() && *length > 0.0) {
+                    return invalid("length must be positive");
+                }
+            },
+            SegmentSpec::Circle{radius, arc} => {
+                if !(radius.is_finite() && *radius > 0.0) {
+                    return invalid("radius must be positive");
+                }
+                if !(arc.is_finite() && *arc != 0.0) {
+                    return invalid("arc must be non-zero");
+                }
+            },
+            SegmentSpec::Rotate{angle, radius} => {
+                if !(angle.is_finite() && *angle != 0.0) {
+                    return invalid("angle must be non-zero");
+                }
+                if !(radius.is_finite() && *radius > 0.0) {
+                    return invalid("radius must be positive");
+                }
+            },
+            SegmentSpec::Clothoid{length, start_curvature, end_curvature} => {
+                if !(length.is_finite() && *length > 0.0) {
+                    return invalid("length must be positive");
+                }
+                if !(start_curvature.is_finite() && end_curvature.is_finite()) {
+                    return invalid("curvature must be finite");
+                }
+            },
+            SegmentSpec::Bezier{points} => {
+                if !points.iter().flatten().all(|coordinate| coordinate.is_finite()) {
+                    return invalid("control points must be finite");
+                }
+                if points.iter().all(|point| point == &points[0]) {
+                    return invalid("control points must not coincide");
+                }
+            },
+            SegmentSpec::Reverse{segment} => segment.validate_at(position)?,
+            SegmentSpec::Compound{segments} => {
+                if segments.is_empty() {
+                    return invalid("compound segment is empty");
+                }
+                for (index, segment) in segments.iter().enumerate() {
+                    position.push(index);
+                    segment.validate_at(position)?;
+                    position.pop();
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // Assumes a valid spec
+    pub fn build(&self) -> Box<dyn PathSegment>
+    {
+        match self {
+            SegmentSpec::Linear{length} => Box::new(LinearSegment::new(*length)),
+            SegmentSpec::Circle{radius, arc} => Box::new(CircleSegment::new(*radius, *arc)),
+            SegmentSpec::Rotate{angle, radius} => Box::new(RotateSegment::new(*angle, *radius)),
+            SegmentSpec::Clothoid{length, start_curvature, end_curvature} =>
+                Box::new(ClothoidSegment::new(*length, *start_curvature, *end_curvature)),
+            SegmentSpec::Bezier{points} => {
+                let point = |index: usize| Vector::new(points[index][0], points[index][1]);
+                Box::new(BezierSegment::new(point(0), point(1), point(2), point(3)))
+            },
+            SegmentSpec::Reverse{segment} => Box::new(ReverseSegment::new(segment.build())),
+            SegmentSpec::Compound{segments} => Box::new(PathFile::build_path(segments)),
+        }
+    }
+}
+
+impl PathFile {
+
+    pub fn from_path(path: &CompoundPath) -> Result<PathFile, PathFileError>
+    {
+        match path.spec().ok_or(PathFileError::Unsupported)? {
+            SegmentSpec::Compound{segments} => Ok(PathFile{segments}),
+            segment => Ok(PathFile{segments: vec![segment]}),
+        }
+    }
+
+    pub fn parse(text: &str, format: Format) -> Result<PathFile, PathFileError>
+    {
+        match format {
+            Format::Json => serde_json::from_str(text).map_err(|error| PathFileError::Parse(error.to_string())),
+            Format::Toml => toml::from_str(text).map_err(|error| PathFileError::Parse(error.to_string())),
+            Format::Yaml => serde_yaml::from_str(text).map_err(|error| PathFileError::Parse(error.to_string())),
+            Format::Script => pathscript::parse_segments(text)
+                .map(|segments| PathFile{segments})
+                .map_err(|error| PathFileError::Parse(error.to_string())),
+        }
+    }
+
+    pub fn to_string(&self, format: Format) -> Result<String, PathFileError>
+    {
+        match format {
+            Format::Json => serde_json::to_string_pretty(self).map_err(|error| PathFileError::Serialize(error.to_string())),
+            Format::Toml => toml::to_string(self).map_err(|error| PathFileError::Serialize(error.to_string())),
+            Format::Yaml => serde_yaml::to_string(self).map_err(|error| PathFileError::Serialize(error.to_string())),
+            Format::Script => Ok(pathscript::format(&self.segments)),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), PathFileError>
+    {
+        SegmentSpec::Compound{segments: self.segments.clone()}.validate()
+            .map_err(|error| match error {
+                // the file itself is no segment, so
+                // the positions start at its segments
+                PathFileError::Invalid{segment, ..} if segment.is_empty() => PathFileError::Invalid{segment, reason: "path has no segments"},
+                error => error,
+            })
+    }
+
+    pub fn build(&self) -> Result<CompoundPath, PathFileError>
+    {
+        self.validate()?;
+        let segments = self.segments.iter().map(|segment| segment.build()).collect();
+        CompoundPath::try_from_segments(Pose::origin(), segments)
+            .map_err(|error| match error {
+                PathError::InvalidSegment{index} => PathFileError::Invalid{segment: vec![index], reason: "segment is empty or not finite"},
+                error => PathFileError::Parse(error.to_string()),
+            })
+    }
+
+    fn build_path(segments: &[SegmentSpec]) -> CompoundPath
+    {
+        let mut path = CompoundPath::new();
+        for segment in segments {
+            path.push(segment.build());
+        }
+        path
+    }
+}
+
+// Reads, validates and builds the path
+// stored in the given file.
+pub fn load(path: &Path) -> Result<CompoundPath, PathFileError>
+{
+    let format = Format::from_path(path)?;
+    let text = fs::read_to_string(path)?;
+    PathFile::parse(&text, format)?.build()
+}
+
+pub fn save(path: &Path, compound_path: &CompoundPath) -> Result<(), PathFileError>
+{
+    let format = Format::from_path(path)?;
+    let text = PathFile::from_path(compound_path)?.to_string(format)?;
+    fs::write(path, text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use crate::path::{Rotation, Vector};
+
+    const JSON: &str = r#"{"segments": [
+        {"type": "linear", "length": 10.0},
+        {"type": "compound", "segments": [
+            {"type": "circle", "radius": 4.0, "arc": 1.5707963267948966},
+            {"type": "linear", "length": 1.0}
+        ]}
+    ]}"#;
+
+    const TOML: &str = r#"
+        [[segments]]
+        type = "linear"
+        length = 10.0
+
+        [[segments]]
+        type = "compound"
+
+        [[segments.segments]]
+        type = "circle"
+        radius = 4.0
+        arc = 1.5707963267948966
+
+        [[segments.segments]]
+        type = "linear"
+        length = 1.0
+    "#;
+
+    const YAML: &str = r#"
+segments:
+  - type: linear
+    length: 10.0
+  - type: compound
+    segments:
+      - type: circle
+        radius: 4.0
+        arc: 1.5707963267948966
+      - type: linear
+        length: 1.0
+"#;
+
+    fn expected() -> PathFile
+    {
+        PathFile{segments: vec![
+            SegmentSpec::Linear{length: 10.0},
+            SegmentSpec::Compound{segments: vec![
+                SegmentSpec::Circle{radius: 4.0, arc: PI / 2.0},
+                SegmentSpec::Linear{length: 1.0},
+            ]},
+        ]}
+    }
+
+    #[test]
+    fn parse_formats() {
+        assert_eq!(PathFile::parse(JSON, Format::Json).unwrap(), expected());
+        assert_eq!(PathFile::parse(TOML, Format::Toml).unwrap(), expected());
+        assert_eq!(PathFile::parse(YAML, Format::Yaml).unwrap(), expected());
+        let script = "forward 10\n{ left 90 r=4; forward 1 }";
+        assert_eq!(PathFile::parse(script, Format::Script).unwrap(), expected());
+    }
+
+    #[test]
+    fn round_trip_through_compound_path() {
+        let path = expected().build().unwrap();
+        assert_eq!(path.length(), 11.0 + 2.0 * PI);
+        let (pos, _) = path.at(1.0);
+        assert!((pos - Vector::new(14.0, 5.0)).norm() < 0.0001);
+        assert_eq!(PathFile::from_path(&path).unwrap(), expected());
+        for format in [Format::Json, Format::Toml, Format::Yaml].iter() {
+            let text = PathFile::from_path(&path).unwrap().to_string(*format).unwrap();
+            assert_eq!(PathFile::parse(&text, *format).unwrap(), expected());
+        }
+    }
+
+    #[test]
+    fn invalid_segments_are_located() {
+        let mut file = expected();
+        file.segments[1] = SegmentSpec::Compound{segments: vec![
+            SegmentSpec::Linear{length: 1.0},
+            SegmentSpec::Circle{radius: 4.0, arc: 0.0},
+        ]};
+        let error = file.build().unwrap_err();
+        assert!(matches!(error, PathFileError::Invalid{ref segment, ..} if *segment == vec![1, 1]));
+        assert_eq!(error.to_string(), "segment 1.1: arc must be non-zero");
+
+        let file = PathFile{segments: vec![SegmentSpec::Linear{length: f64::NAN}]};
+        assert!(matches!(file.build(), Err(PathFileError::Invalid{..})));
+        let file = PathFile{segments: vec![]};
+        assert!(matches!(file.build(), Err(PathFileError::Invalid{..})));
+    }
+
+    #[test]
+    fn reverse_segments() {
+        let text = r#"{"segments": [
+            {"type": "linear", "length": 10.0},
+            {"type": "reverse", "segment": {"type": "linear", "length": 4.0}}
+        ]}"#;
+        let file = PathFile::parse(text, Format::Json).unwrap();
+        let path = file.build().unwrap();
+        let (pos, _) = path.at(1.0);
+        assert!((pos - Vector::new(6.0, 0.0)).norm() < 0.0001);
+        assert_eq!(PathFile::from_path(&path).unwrap(), file);
+
+        let file = PathFile{segments: vec![
+            SegmentSpec::Reverse{segment: Box::new(SegmentSpec::Linear{length: -1.0})},
+        ]};
+        assert!(matches!(file.build(), Err(PathFileError::Invalid{ref segment, ..}) if *segment == vec![0]));
+    }
+
+    #[test]
+    fn segments_without_spec_are_unsupported() {
+        struct Wiggle;
+
+        impl PathSegment for Wiggle
+        {
+            fn length(&self) -> f64
+            {
+                1.0
+            }
+
+            fn at(&self, position: f64) -> (Vector, Rotation)
+            {
+                (Vector::new(position, (position * PI).sin()), Rotation::new(0.0))
+            }
+        }
+
+        let mut path = expected().build().unwrap();
+        path.push(Box::new(Wiggle));
+        assert!(matches!(PathFile::from_path(&path), Err(PathFileError::Unsupported)));
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        let text = r#"{"segments": [{"type": "spiral", "length": 10.0}]}"#;
+        assert!(matches!(PathFile::parse(text, Format::Json), Err(PathFileError::Parse(_))));
+        assert!(matches!(Format::from_path(Path::new("course.txt")), Err(PathFileError::UnknownFormat)));
+        assert_eq!(Format::from_path(Path::new("courses/l-turn.toml")).unwrap(), Format::Toml);
+        assert_eq!(Format::from_path(Path::new("courses/l-turn.yml")).unwrap(), Format::Yaml);
+    }
+}
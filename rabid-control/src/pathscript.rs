@@ -0,0 +1,442 @@
+// A small language to describe paths.
+//
+// Each statement is a command with its arguments,
+// separated by semicolons or new lines:
+//
+//   forward 100; left 90 r=20
+//   spin -45     # turn on the spot
+//   back 30
+//
+// Angles are given in degrees, lengths in cm.
+//
+//   forward L                 drive straight
+//   back L                    back up straight
+//   left A r=R, right A r=R   drive a circle
+//   spin A [r=R]              turn on the spot, positive turns left
+//   clothoid L from=K to=K    change curvature (1/cm) over L
+//   bezier X,Y X,Y X,Y X,Y    the control points of a curve
+//   reverse STATEMENT         drive a statement backwards
+//   { STATEMENTS }            a nested path
+//
+// Everything after # up to the end of the line is a comment.
+// Scripts describe the same segments as path files, and
+// paths can be formatted back into a script.
+use std::fmt;
+
+use crate::path::{CompoundPath, SegmentSpec};
+use crate::pathfile::{PathFile, PathFileError};
+use crate::twowheel::WHEELBASE;
+
+// Half the wheelbase of the robot as built,
+// used when spin doesn't give a radius.
+pub const SPIN_RADIUS: f64 = WHEELBASE / 2.0;
+// Angles are formatted to this many
+// decimals of a degree.
+const DEGREE_DECIMALS: i32 = 9;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptError
+{
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token
+{
+    Word(String),
+    Number(f64),
+    Equals,
+    Comma,
+    // a semicolon or a new line
+    Separator,
+    Open,
+    Close,
+    End,
+}
+
+#[derive(Clone, Debug)]
+struct Lexeme
+{
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+impl Lexeme {
+
+    fn error(&self, message: String) -> ScriptError
+    {
+        ScriptError{line: self.line, column: self.column, message}
+    }
+}
+
+fn lex(text: &str) -> Result<Vec<Lexeme>, ScriptError>
+{
+    let mut lexemes = Vec::new();
+    let mut chars = text.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+    while let Some(&c) = chars.peek() {
+        let (start_line, start_column) = (line, column);
+        let mut lexeme = |token| lexemes.push(Lexeme{token, line: start_line, column: start_column});
+        if c.is_alphabetic() {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_alphanumeric() || c == '_') {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+                column += 1;
+            }
+            lexeme(Token::Word(word));
+            continue;
+        }
+        if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                let exponent_sign = (c == '-' || c == '+') && number.ends_with(['e', 'E']);
+                if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || exponent_sign
+                     || (number.is_empty() && (c == '-' || c == '+'))) {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+                column += 1;
+            }
+            match number.parse() {
+                Ok(value) => lexeme(Token::Number(value)),
+                Err(_) => return Err(ScriptError{
+                    line: start_line, column: start_column, message: format!("malformed number {}", number)
+                }),
+            }
+            continue;
+        }
+        chars.next();
+        column += 1;
+        match c {
+            '\n' => {
+                lexeme(Token::Separator);
+                line += 1;
+                column = 1;
+            },
+            ';' => lexeme(Token::Separator),
+            '=' => lexeme(Token::Equals),
+            ',' => lexeme(Token::Comma),
+            '{' => lexeme(Token::Open),
+            '}' => lexeme(Token::Close),
+            '#' => {
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    chars.next();
+                    column += 1;
+                }
+            },
+            c if c.is_whitespace() => (),
+            c => return Err(ScriptError{
+                line: start_line, column: start_column, message: format!("unexpected character {:?}", c)
+            }),
+        }
+    }
+    lexemes.push(Lexeme{token: Token::End, line, column});
+    Ok(lexemes)
+}
+
+struct Parser
+{
+    lexemes: Vec<Lexeme>,
+    index: usize,
+}
+
+impl Parser {
+
+    fn peek(&self) -> &Lexeme
+    {
+        &self.lexemes[self.index]
+    }
+
+    fn next(&mut self) -> Lexeme
+    {
+        let lexeme = self.lexemes[self.index].clone();
+        // End stays put
+        if self.index + 1 < self.lexemes.len() {
+            self.index += 1;
+        }
+        lexeme
+    }
+
+    fn number(&mut self) -> Result<f64, ScriptError>
+    {
+        let lexeme = self.next();
+        match lexeme.token {
+            Token::Number(value) => Ok(value),
+            _ => Err(lexeme.error("expected a number".to_string())),
+        }
+    }
+
+    fn expect(&mut self, token: Token, message: &str) -> Result<(), ScriptError>
+    {
+        let lexeme = self.next();
+        if lexeme.token == token {
+            Ok(())
+        }
+        else
+        {
+            Err(lexeme.error(message.to_string()))
+        }
+    }
+
+    // Options given as name=value, in the
+    // order of the names asked for.
+    fn options(&mut self, names: &[&str]) -> Result<Vec<Option<f64>>, ScriptError>
+    {
+        let mut values = vec![None; names.len()];
+        while let Token::Word(name) = &self.peek().token {
+            let name = name.clone();
+            let lexeme = self.next();
+            let index = names.iter().position(|known| *known == name)
+                .ok_or_else(|| lexeme.error(format!("unknown option {}", name)))?;
+            self.expect(Token::Equals, "expected =")?;
+            values[index] = Some(self.number()?);
+        }
+        Ok(values)
+    }
+
+    fn point(&mut self) -> Result<[f64; 2], ScriptError>
+    {
+        let x = self.number()?;
+        self.expect(Token::Comma, "expected , between coordinates")?;
+        Ok([x, self.number()?])
+    }
+
+    // The segments, each with the lexeme it starts at
+    fn statements(&mut self, nested: bool) -> Result<Vec<(Lexeme, SegmentSpec)>, ScriptError>
+    {
+        let mut segments = Vec::new();
+        loop {
+            let lexeme = self.peek().clone();
+            match lexeme.token {
+                Token::Separator => {
+                    self.next();
+                    continue;
+                },
+                Token::End if nested => return Err(lexeme.error("missing }".to_string())),
+                Token::End => break,
+                Token::Close if nested => {
+                    self.next();
+                    break;
+                },
+                Token::Close => return Err(lexeme.error("unexpected }".to_string())),
+                _ => segments.push((lexeme, self.statement()?)),
+            }
+            let lexeme = self.peek();
+            match lexeme.token {
+                Token::Separator | Token::Close | Token::End => (),
+                _ => return Err(lexeme.error("expected ; or a new line".to_string())),
+            }
+        }
+        Ok(segments)
+    }
+
+    fn statement(&mut self) -> Result<SegmentSpec, ScriptError>
+    {
+        let start = self.next();
+        let required = |value: Option<f64>, name: &str| value.ok_or_else(|| start.error(format!("missing {}=", name)));
+        let segment = match &start.token {
+            Token::Open => SegmentSpec::Compound{
+                segments: self.statements(true)?.into_iter().map(|(_, segment)| segment).collect()
+            },
+            Token::Word(command) => match command.as_str() {
+                "forward" => SegmentSpec::Linear{length: self.number()?},
+                "back" => SegmentSpec::Reverse{segment: Box::new(SegmentSpec::Linear{length: self.number()?})},
+                "left" | "right" => {
+                    let angle = self.number()?.to_radians();
+                    let radius = required(self.options(&["r"])?[0], "r")?;
+                    let arc = if command.as_str() == "left" { angle } else { -angle };
+                    SegmentSpec::Circle{radius, arc}
+                },
+                "spin" => {
+                    let angle = self.number()?.to_radians();
+                    let radius = self.options(&["r"])?[0].unwrap_or(SPIN_RADIUS);
+                    SegmentSpec::Rotate{angle, radius}
+                },
+                "clothoid" => {
+                    let length = self.number()?;
+                    let options = self.options(&["from", "to"])?;
+                    SegmentSpec::Clothoid{
+                        length,
+                        start_curvature: required(options[0], "from")?,
+                        end_curvature: required(options[1], "to")?,
+                    }
+                },
+                "bezier" => SegmentSpec::Bezier{
+                    points: [self.point()?, self.point()?, self.point()?, self.point()?]
+                },
+                "reverse" => SegmentSpec::Reverse{segment: Box::new(self.statement()?)},
+                command => return Err(start.error(format!("unknown command {}", command))),
+            },
+            _ => return Err(start.error("expected a command".to_string())),
+        };
+        segment.validate().map_err(|error| match error {
+            PathFileError::Invalid{reason, ..} => start.error(reason.to_string()),
+            error => start.error(error.to_string()),
+        })?;
+        Ok(segment)
+    }
+}
+
+fn parse_statements(text: &str) -> Result<Vec<(Lexeme, SegmentSpec)>, ScriptError>
+{
+    let mut parser = Parser{lexemes: lex(text)?, index: 0};
+    let statements = parser.statements(false)?;
+    if statements.is_empty() {
+        return Err(parser.peek().error("path has no segments".to_string()));
+    }
+    Ok(statements)
+}
+
+// Parses a script into the segments it describes.
+pub fn parse_segments(text: &str) -> Result<Vec<SegmentSpec>, ScriptError>
+{
+    Ok(parse_statements(text)?.into_iter().map(|(_, segment)| segment).collect())
+}
+
+pub fn parse(text: &str) -> Result<CompoundPath, ScriptError>
+{
+    let statements = parse_statements(text)?;
+    let segments = statements.iter().map(|(_, segment)| segment.clone()).collect();
+    // errors point at the top level statement
+    // the offending segment belongs to
+    PathFile{segments}.build().map_err(|error| match error {
+        PathFileError::Invalid{segment, reason} if !segment.is_empty() =>
+            statements[segment[0]].0.error(reason.to_string()),
+        error => statements[0].0.error(error.to_string()),
+    })
+}
+
+fn degrees(radians: f64) -> f64
+{
+    let scale = 10f64.powi(DEGREE_DECIMALS);
+    (radians.to_degrees() * scale).round() / scale
+}
+
+fn format_segment(segment: &SegmentSpec) -> String
+{
+    match segment {
+        SegmentSpec::Linear{length} => format!("forward {}", length),
+        SegmentSpec::Circle{radius, arc} => {
+            let command = if *arc >= 0.0 { "left" } else { "right" };
+            format!("{} {} r={}", command, degrees(arc.abs()), radius)
+        },
+        SegmentSpec::Rotate{angle, radius} if *radius == SPIN_RADIUS => format!("spin {}", degrees(*angle)),
+        SegmentSpec::Rotate{angle, radius} => format!("spin {} r={}", degrees(*angle), radius),
+        SegmentSpec::Clothoid{length, start_curvature, end_curvature} =>
+            format!("clothoid {} from={} to={}", length, start_curvature, end_curvature),
+        SegmentSpec::Bezier{points} => {
+            let points: Vec<String> = points.iter().map(|[x, y]| format!("{},{}", x, y)).collect();
+            format!("bezier {}", points.join(" "))
+        },
+        SegmentSpec::Reverse{segment} => match segment.as_ref() {
+            SegmentSpec::Linear{length} => format!("back {}", length),
+            segment => format!("reverse {}", format_segment(segment)),
+        },
+        SegmentSpec::Compound{segments} => {
+            let segments: Vec<String> = segments.iter().map(format_segment).collect();
+            format!("{{ {} }}", segments.join("; "))
+        },
+    }
+}
+
+// One statement per line
+pub fn format(segments: &[SegmentSpec]) -> String
+{
+    segments.iter().map(|segment| format_segment(segment) + "\n").collect()
+}
+
+pub fn format_path(path: &CompoundPath) -> Result<String, PathFileError>
+{
+    Ok(format(&PathFile::from_path(path)?.segments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use crate::path::{PathSegment, Vector};
+
+    #[test]
+    fn parse_example() {
+        let segments = parse_segments("forward 100; left 90 r=20; spin -45; back 30").unwrap();
+        assert_eq!(segments, vec![
+            SegmentSpec::Linear{length: 100.0},
+            SegmentSpec::Circle{radius: 20.0, arc: PI / 2.0},
+            SegmentSpec::Rotate{angle: -PI / 4.0, radius: SPIN_RADIUS},
+            SegmentSpec::Reverse{segment: Box::new(SegmentSpec::Linear{length: 30.0})},
+        ]);
+        let path = parse("forward 100; left 90 r=20; spin -45; back 30").unwrap();
+        let (pos, _) = path.at(1.0);
+        let end = Vector::new(120.0, 20.0) - Vector::new(30.0, 30.0) / 2f64.sqrt();
+        assert!((pos - end).norm() < 0.0001);
+    }
+
+    #[test]
+    fn all_statements() {
+        let text = "
+            # a bit of everything
+            clothoid 10 from=0 to=0.05
+            right 45 r=20
+            bezier 0,0 10,0 20,5 30,5
+            reverse { left 90 r=10; spin 180 r=10 }
+        ";
+        let segments = parse_segments(text).unwrap();
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[0], SegmentSpec::Clothoid{length: 10.0, start_curvature: 0.0, end_curvature: 0.05});
+        assert_eq!(segments[2], SegmentSpec::Bezier{points: [[0.0, 0.0], [10.0, 0.0], [20.0, 5.0], [30.0, 5.0]]});
+        assert!(matches!(&segments[3], SegmentSpec::Reverse{segment} if matches!(segment.as_ref(), SegmentSpec::Compound{..})));
+    }
+
+    #[test]
+    fn round_trip_formatting() {
+        let text = "forward 100\nleft 90 r=20\nspin -45\nspin 30 r=5\nback 30\n\
+                    clothoid 10 from=0 to=0.05\nright 45.5 r=20\nbezier 0,0 10,0 20,5 30,5\n\
+                    reverse { left 90 r=10; forward 2.5 }\n";
+        let segments = parse_segments(text).unwrap();
+        assert_eq!(format(&segments), text);
+        let path = parse(text).unwrap();
+        assert_eq!(parse_segments(&format_path(&path).unwrap()).unwrap(), segments);
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let error = |text| parse_segments(text).unwrap_err();
+        assert_eq!(error("forward 10\n  jump 3"), ScriptError{line: 2, column: 3, message: "unknown command jump".to_string()});
+        assert_eq!(error("left 90").to_string(), "1:1: missing r=");
+        assert_eq!(error("forward 10 20").to_string(), "1:12: expected ; or a new line");
+        assert_eq!(error("forward -1").to_string(), "1:1: length must be positive");
+        assert_eq!(error("{ forward 1").to_string(), "1:12: missing }");
+        assert_eq!(error("forward 1 }").to_string(), "1:11: unexpected }");
+        assert_eq!(error("left 90 radius=3").to_string(), "1:9: unknown option radius");
+        assert_eq!(error("forward 1.2.3").to_string(), "1:9: malformed number 1.2.3");
+        assert_eq!(error("forward x").to_string(), "1:9: expected a number");
+        assert_eq!(error("# nothing\n").to_string(), "2:1: path has no segments");
+    }
+
+    #[test]
+    fn build_errors_have_positions() {
+        // valid control points, but the curve is
+        // too long to be measured
+        let error = parse("forward 10\n  bezier 0,0 1e308,0 -1e308,0 1e308,0").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 3));
+    }
+}
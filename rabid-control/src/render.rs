@@ -0,0 +1,248 @@
+// Rendering paths to SVG.
+//
+// The path is sampled, and drawn together with
+// the tracks the two wheels of the robot leave
+// behind. Segment boundaries are marked, and arrows
+// show the heading along the way. Everything is
+// drawn on a grid in centimeters, so a course can be
+// checked against the floor it's meant for.
+use std::fmt;
+use std::fmt::Write;
+
+use crate::path::{CompoundPath, PathSegment, Vector};
+use crate::twowheel::TwoWheelRobot;
+
+// The area the robot may drive in, in cm
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Arena
+{
+    pub min: Vector,
+    pub max: Vector,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings
+{
+    // How many points the path and the
+    // tracks are sampled at
+    pub samples: usize,
+    // How many heading arrows are drawn
+    pub arrows: usize,
+    // The spacing of the grid lines in cm
+    pub grid: f64,
+    pub arena: Option<Arena>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderError
+{
+    // The grid spacing must be a positive number
+    InvalidGrid(f64),
+    // The path or the arena reach out to infinity
+    Unbounded,
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::InvalidGrid(grid) => write!(f, "grid spacing must be positive, not {}", grid),
+            RenderError::Unbounded => write!(f, "the drawing would be infinitely large"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings{samples: 200, arrows: 10, grid: 10.0, arena: None}
+    }
+}
+
+impl Arena {
+
+    pub fn new(min: Vector, max: Vector) -> Arena
+    {
+        Arena{min, max}
+    }
+
+    // Grows the arena so that it
+    // contains the given point.
+    fn include(&mut self, point: &Vector)
+    {
+        self.min = Vector::new(self.min[0].min(point[0]), self.min[1].min(point[1]));
+        self.max = Vector::new(self.max[0].max(point[0]), self.max[1].max(point[1]));
+    }
+
+    pub fn contains(&self, point: &Vector) -> bool
+    {
+        self.min[0] <= point[0] && point[0] <= self.max[0] &&
+            self.min[1] <= point[1] && point[1] <= self.max[1]
+    }
+}
+
+fn polyline(points: &[Vector], class: &str) -> String
+{
+    let points: Vec<String> = points.iter().map(|p| format!("{:.2},{:.2}", p[0], p[1])).collect();
+    format!("<polyline class=\"{}\" points=\"{}\"/>\n", class, points.join(" "))
+}
+
+// Renders the path as a complete SVG document.
+pub fn render_svg(path: &CompoundPath, robot: &TwoWheelRobot, settings: &RenderSettings) -> Result<String, RenderError>
+{
+    let grid = settings.grid;
+    if !(grid.is_finite() && grid > 0.0) {
+        return Err(RenderError::InvalidGrid(grid));
+    }
+    let samples = settings.samples.max(2);
+    let mut centre = Vec::with_capacity(samples + 1);
+    let mut left = Vec::with_capacity(samples + 1);
+    let mut right = Vec::with_capacity(samples + 1);
+    for step in 0..=samples {
+        let position = step as f64 / samples as f64;
+        let (pos, _) = path.at(position);
+        let wheels = robot.wheel_position_at(path, position);
+        centre.push(pos);
+        left.push(wheels.left);
+        right.push(wheels.right);
+    }
+
+    // The drawing covers the tracks and the arena,
+    // extended to the next grid line plus one.
+    let mut bounds = Arena::new(centre[0], centre[0]);
+    for point in centre.iter().chain(left.iter()).chain(right.iter()) {
+        bounds.include(point);
+    }
+    if let Some(arena) = settings.arena {
+        bounds.include(&arena.min);
+        bounds.include(&arena.max);
+    }
+    let min = Vector::new(
+        (bounds.min[0] / grid).floor() * grid - grid,
+        (bounds.min[1] / grid).floor() * grid - grid);
+    let max = Vector::new(
+        (bounds.max[0] / grid).ceil() * grid + grid,
+        (bounds.max[1] / grid).ceil() * grid + grid);
+    let size = max - min;
+    if !(size[0].is_finite() && size[1].is_finite()) {
+        return Err(RenderError::Unbounded);
+    }
+
+    let mut svg = String::new();
+    // write! on a String can't fail
+    let _ = writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}cm\" height=\"{}cm\" viewBox=\"{} {} {} {}\">",
+                     size[0], size[1], min[0], -max[1], size[0], size[1]);
+    svg.push_str("<style>\n\
+                  .grid { stroke: #ddd; stroke-width: 0.2; }\n\
+                  .arena { fill: none; stroke: #000; stroke-width: 0.8; }\n\
+                  .path { fill: none; stroke: #00f; stroke-width: 0.5; }\n\
+                  .track { fill: none; stroke: #888; stroke-width: 0.3; stroke-dasharray: 1,1; }\n\
+                  .boundary { fill: #f00; }\n\
+                  .heading { stroke: #080; stroke-width: 0.4; }\n\
+                  </style>\n");
+    // SVG has y pointing down, our paths up
+    svg.push_str("<g transform=\"scale(1,-1)\">\n");
+
+    // counted rather than stepped, so rounding
+    // can't add or lose a line
+    let columns = (size[0] / grid).round() as usize;
+    for column in 0..=columns {
+        let x = min[0] + column as f64 * grid;
+        let _ = writeln!(svg, "<line class=\"grid\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>", x, min[1], x, max[1]);
+    }
+    let rows = (size[1] / grid).round() as usize;
+    for row in 0..=rows {
+        let y = min[1] + row as f64 * grid;
+        let _ = writeln!(svg, "<line class=\"grid\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"/>", min[0], y, max[0], y);
+    }
+
+    if let Some(arena) = settings.arena {
+        let extent = arena.max - arena.min;
+        let _ = writeln!(svg, "<rect class=\"arena\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"/>",
+                         arena.min[0], arena.min[1], extent[0], extent[1]);
+    }
+
+    svg.push_str(&polyline(&left, "track"));
+    svg.push_str(&polyline(&right, "track"));
+    svg.push_str(&polyline(&centre, "path"));
+
+    let mut boundaries = path.boundaries();
+    boundaries.push(1.0);
+    for boundary in boundaries {
+        let (pos, _) = path.at(boundary);
+        let _ = writeln!(svg, "<circle class=\"boundary\" cx=\"{:.2}\" cy=\"{:.2}\" r=\"1\"/>", pos[0], pos[1]);
+    }
+
+    // The arrows are as long as the robot is wide
+    let arrow_length = robot.wheelbase() / 2.0;
+    for arrow in 0..settings.arrows {
+        let (pos, rot) = path.at((arrow as f64 + 0.5) / settings.arrows as f64);
+        let tip = pos + rot.transform_vector(&Vector::new(arrow_length, 0.0));
+        let barb = rot.transform_vector(&Vector::new(-arrow_length / 3.0, arrow_length / 6.0));
+        let barb2 = rot.transform_vector(&Vector::new(-arrow_length / 3.0, -arrow_length / 6.0));
+        let _ = writeln!(svg, "<path class=\"heading\" d=\"M {:.2} {:.2} L {:.2} {:.2} M {:.2} {:.2} L {:.2} {:.2} L {:.2} {:.2}\"/>",
+                         pos[0], pos[1], tip[0], tip[1],
+                         tip[0] + barb[0], tip[1] + barb[1], tip[0], tip[1],
+                         tip[0] + barb2[0], tip[1] + barb2[1]);
+    }
+
+    svg.push_str("</g>\n</svg>\n");
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfile::{PathFile, SegmentSpec};
+    use std::f64::consts::PI;
+
+    fn l_turn() -> CompoundPath
+    {
+        PathFile{segments: vec![
+            SegmentSpec::Linear{length: 10.0},
+            SegmentSpec::Circle{radius: 4.0, arc: PI / 2.0},
+            SegmentSpec::Linear{length: 1.0},
+        ]}.build().unwrap()
+    }
+
+    #[test]
+    fn renders_all_elements() {
+        let robot = TwoWheelRobot::new(23.5, 10.0);
+        let settings = RenderSettings{
+            arena: Some(Arena::new(Vector::new(-20.0, -20.0), Vector::new(50.0, 50.0))),
+            ..RenderSettings::default()
+        };
+        let svg = render_svg(&l_turn(), &robot, &settings).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("class=\"track\"").count(), 2);
+        assert_eq!(svg.matches("class=\"path\"").count(), 1);
+        // three segments, so four boundaries
+        assert_eq!(svg.matches("class=\"boundary\"").count(), 4);
+        assert_eq!(svg.matches("class=\"heading\"").count(), settings.arrows);
+        assert_eq!(svg.matches("class=\"arena\"").count(), 1);
+        // the arena, -20..50 grown by one grid line
+        assert!(svg.contains("viewBox=\"-30 -60 90 90\""));
+    }
+
+    #[test]
+    fn arena_is_optional() {
+        let robot = TwoWheelRobot::new(23.5, 10.0);
+        let svg = render_svg(&l_turn(), &robot, &RenderSettings::default()).unwrap();
+        assert!(!svg.contains("class=\"arena\""));
+    }
+
+    #[test]
+    fn broken_grids_are_rejected() {
+        let robot = TwoWheelRobot::new(23.5, 10.0);
+        for grid in [0.0, -10.0, f64::NAN, f64::INFINITY].iter() {
+            let settings = RenderSettings{grid: *grid, ..RenderSettings::default()};
+            assert!(matches!(render_svg(&l_turn(), &robot, &settings), Err(RenderError::InvalidGrid(_))));
+        }
+        let settings = RenderSettings{
+            arena: Some(Arena::new(Vector::new(f64::NEG_INFINITY, 0.0), Vector::new(50.0, 50.0))),
+            ..RenderSettings::default()
+        };
+        assert_eq!(render_svg(&l_turn(), &robot, &settings), Err(RenderError::Unbounded));
+    }
+}
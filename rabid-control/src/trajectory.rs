@@ -0,0 +1,185 @@
+// Timing a path.
+//
+// A path is split into its runs, the stretches driven
+// in one direction. Each run gets its own Ramp, so the
+// robot comes to a full stop before it changes
+// direction. The wheel velocities follow from how fast
+// the wheels move along the path, and are negative
+// for wheels turning backwards.
+use std::time::Duration;
+
+use crate::path::{Direction, PathSegment, Ramp, Vector};
+use crate::twowheel::TwoWheelRobot;
+
+// The relative step used to find how
+// the wheels move along the path.
+const TRAJECTORY_STEP: f64 = 1e-6;
+
+struct Run
+{
+    // relative positions along the path
+    start: f64,
+    end: f64,
+    direction: Direction,
+    ramp: Ramp,
+}
+
+pub struct Trajectory<'a>
+{
+    path: &'a dyn PathSegment,
+    robot: TwoWheelRobot,
+    runs: Vec<Run>,
+}
+
+impl<'a> Trajectory<'a> {
+
+    // Velocity in cm/s, acceleration in cm/s^2,
+    // both applied along the extent of the path.
+    pub fn new(path: &'a dyn PathSegment, robot: TwoWheelRobot, max_velocity: f64, max_acceleration: f64) -> Trajectory<'a>
+    {
+        let runs = path.runs();
+        let extent = path.extent();
+        let ends: Vec<f64> = runs.iter().skip(1).map(|(start, _)| *start).chain(Some(1.0)).collect();
+        let runs = runs.into_iter().zip(ends).map(|((start, direction), end)| Run{
+            start,
+            end,
+            direction,
+            ramp: Ramp::new((end - start) * extent, max_velocity, max_acceleration),
+        }).collect();
+        Trajectory{path, robot, runs}
+    }
+
+    pub fn duration(&self) -> Duration
+    {
+        self.runs.iter().map(|run| run.ramp.total_duration()).sum()
+    }
+
+    // The run driven at the given time, and
+    // how long it has been driven.
+    fn run_at(&self, when: Duration) -> (&Run, Duration)
+    {
+        let mut start = Duration::from_secs(0);
+        for run in self.runs.iter() {
+            let end = start + run.ramp.total_duration();
+            if when < end {
+                return (run, when - start);
+            }
+            start = end;
+        }
+        let last = self.runs.last().expect("a path has at least one run");
+        (last, last.ramp.total_duration())
+    }
+
+    fn relative_position(&self, run: &Run, when: Duration) -> f64
+    {
+        let extent = self.path.extent();
+        if extent > 0.0 {
+            (run.start + run.ramp.position_at_duration(when) / extent).min(run.end)
+        }
+        else
+        {
+            run.start
+        }
+    }
+
+    // The relative position along the path
+    pub fn position_at(&self, when: Duration) -> f64
+    {
+        let (run, when) = self.run_at(when);
+        self.relative_position(run, when)
+    }
+
+    pub fn direction_at(&self, when: Duration) -> Direction
+    {
+        self.run_at(when).0.direction
+    }
+
+    // Left and right wheel velocities in cm/s
+    pub fn wheel_velocities_at(&self, when: Duration) -> (f64, f64)
+    {
+        let extent = self.path.extent();
+        let (run, when) = self.run_at(when);
+        let position = self.relative_position(run, when);
+        // Stay inside the run, where the
+        // wheels move the same way.
+        let before = (position - TRAJECTORY_STEP).max(run.start);
+        let after = (position + TRAJECTORY_STEP).min(run.end);
+        if extent <= 0.0 || after <= before {
+            return (0.0, 0.0);
+        }
+        let rate = run.ramp.velocity_at_duration(when) / extent;
+        let (_, rot) = self.path.at(position);
+        let forward = rot.transform_vector(&Vector::new(1.0, 0.0));
+        let start = self.robot.wheel_position_at(self.path, before);
+        let end = self.robot.wheel_position_at(self.path, after);
+        let velocity = |from: Vector, to: Vector| (to - from).dot(&forward) / (after - before) * rate;
+        (velocity(start.left, end.left), velocity(start.right, end.right))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use crate::path::{CircleSegment, CompoundPath, LinearSegment, ReverseSegment, RotateSegment};
+
+    fn robot() -> TwoWheelRobot
+    {
+        TwoWheelRobot::new(20.0, 10.0)
+    }
+
+    #[test]
+    fn straight_path_follows_ramp() {
+        let path = LinearSegment::new(100.0);
+        let trajectory = Trajectory::new(&path, robot(), 30.0, 10.0);
+        let ramp = Ramp::new(100.0, 30.0, 10.0);
+        assert_eq!(trajectory.duration(), ramp.total_duration());
+        let when = Duration::from_secs(2);
+        let (left, right) = trajectory.wheel_velocities_at(when);
+        assert!((left - 20.0).abs() < 0.001 && (right - 20.0).abs() < 0.001);
+        assert!((trajectory.position_at(when) - 0.2).abs() < 1e-9);
+        assert_eq!(trajectory.wheel_velocities_at(trajectory.duration()), (0.0, 0.0));
+    }
+
+    #[test]
+    fn stop_and_reverse() {
+        let mut path = CompoundPath::new();
+        path.push(Box::new(LinearSegment::new(40.0)));
+        path.push(Box::new(ReverseSegment::new(Box::new(LinearSegment::new(40.0)))));
+        let trajectory = Trajectory::new(&path, robot(), 30.0, 10.0);
+        let run = Ramp::new(40.0, 30.0, 10.0).total_duration();
+        assert_eq!(trajectory.duration(), run * 2);
+
+        let (left, right) = trajectory.wheel_velocities_at(run / 2);
+        assert!(left > 10.0 && (left - right).abs() < 0.001);
+        assert_eq!(trajectory.direction_at(run / 2), Direction::Forward);
+        // standing still at the turning point
+        let (left, right) = trajectory.wheel_velocities_at(run);
+        assert!(left.abs() < 0.001 && right.abs() < 0.001);
+        assert!((trajectory.position_at(run) - 0.5).abs() < 1e-9);
+        // and backing up
+        let (left, right) = trajectory.wheel_velocities_at(run + run / 2);
+        assert!(left < -10.0 && (left - right).abs() < 0.001);
+        assert_eq!(trajectory.direction_at(run + run / 2), Direction::Reverse);
+    }
+
+    #[test]
+    fn turning_on_the_spot() {
+        let path = RotateSegment::new(PI, 10.0);
+        let trajectory = Trajectory::new(&path, robot(), 30.0, 10.0);
+        let (left, right) = trajectory.wheel_velocities_at(trajectory.duration() / 2);
+        // turning left, the left wheel goes backwards
+        assert!(left < -10.0);
+        assert!((left + right).abs() < 0.001);
+    }
+
+    #[test]
+    fn outer_wheel_is_faster() {
+        let path = CircleSegment::new(50.0, PI / 2.0);
+        let trajectory = Trajectory::new(&path, robot(), 30.0, 10.0);
+        let (left, right) = trajectory.wheel_velocities_at(trajectory.duration() / 2);
+        assert!(right > left && left > 0.0);
+        // 60cm and 40cm from the centre of the turn
+        assert!((right / left - 1.5).abs() < 0.001);
+    }
+}
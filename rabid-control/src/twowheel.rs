@@ -1,12 +1,17 @@
 use crate::path::{PathSegment, Vector};
 
+// The robot as built, in cm
+pub const WHEELBASE: f64 = 23.5;
+pub const WHEELDIAMETER: f64 = 10.0;
+
 #[derive(Debug)]
 pub struct WheelPositions
 {
-    left: Vector,
-    right: Vector,
+    pub left: Vector,
+    pub right: Vector,
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct TwoWheelRobot
 {
     wheelbase: f64,
@@ -16,9 +21,25 @@ pub struct TwoWheelRobot
 
 impl TwoWheelRobot
 {
+    // Both given in cm
+    pub fn new(wheelbase: f64, wheeldiameter: f64) -> TwoWheelRobot
+    {
+        TwoWheelRobot{wheelbase, wheeldiameter}
+    }
+
+    pub fn wheelbase(&self) -> f64
+    {
+        self.wheelbase
+    }
+
+    pub fn wheeldiameter(&self) -> f64
+    {
+        self.wheeldiameter
+    }
+
     pub fn wheel_position_at(&self, path: &dyn PathSegment, position: f64) -> WheelPositions
     {
-        let left = Vector::new(0.0, -self.wheelbase / 2.0);
+        let left = Vector::new(0.0, self.wheelbase / 2.0);
         let right = -left;
         let (base, rot) = path.at(position);
         let left = rot.transform_vector(&left);
@@ -54,9 +75,9 @@ mod tests {
     {
         let wheelbase = 23.5;
         let radius = 100.0;
-        let robot = TwoWheelRobot{wheelbase: wheelbase, wheeldiameter: 10.0};
+        let robot = TwoWheelRobot{wheelbase, wheeldiameter: 10.0};
         let path = CircleSegment::new(radius, PI * 2.0);
-        let left_offset = Vector::new(0.0, -wheelbase / 2.0);
+        let left_offset = Vector::new(0.0, wheelbase / 2.0);
         let right_offset = -left_offset;
 
         let expected = WheelPositions{left: left_offset, right: right_offset};
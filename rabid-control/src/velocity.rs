@@ -0,0 +1,290 @@
+// Closed-loop wheel speed control.
+//
+// Motor boards like the MD23 only know normalized
+// motor power. How fast
+// the wheels actually turn for a given power depends
+// on battery voltage and load, so each wheel gets a
+// PID controller with feed-forward, comparing the
+// requested speed against the speed measured from
+// the encoders.
+use std::f64::consts::PI;
+use std::time::Instant;
+
+use crate::motor::Telemetry;
+use crate::twowheel::TwoWheelRobot;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gains
+{
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+    // The output expected for one revolution per
+    // second, applied directly to the target.
+    pub kf: f32,
+}
+
+impl Default for Gains {
+    fn default() -> Self {
+        // The robot turns about 3 rps at full power.
+        Gains{kp: 0.2, ki: 0.5, kd: 0.0, kf: 1.0 / 3.0}
+    }
+}
+
+// A PID controller with feed-forward. The output
+// is limited to -1.0..1.0, and the integral stops
+// accumulating while the output is saturated, so it
+// doesn't wind up.
+#[derive(Clone, Debug)]
+pub struct Pid
+{
+    gains: Gains,
+    integral: f32,
+    previous_error: Option<f32>,
+}
+
+impl Pid {
+
+    pub fn new(gains: Gains) -> Pid
+    {
+        Pid{gains, integral: 0.0, previous_error: None}
+    }
+
+    pub fn gains(&self) -> Gains
+    {
+        self.gains
+    }
+
+    pub fn set_gains(&mut self, gains: Gains)
+    {
+        self.gains = gains;
+    }
+
+    pub fn reset(&mut self)
+    {
+        self.integral = 0.0;
+        self.previous_error = None;
+    }
+
+    // dt is the time since the last update in seconds,
+    // the first update after a reset only gets
+    // proportional and feed-forward terms.
+    pub fn update(&mut self, target: f32, measured: f32, dt: f32) -> f32
+    {
+        let Gains{kp, ki, kd, kf} = self.gains;
+        let error = target - measured;
+        let derivative = match self.previous_error {
+            Some(previous) if dt > 0.0 => (error - previous) / dt,
+            _ => 0.0,
+        };
+        self.previous_error = Some(error);
+
+        let without_integral = kf * target + kp * error + kd * derivative;
+        let integral = self.integral + error * dt;
+        let output = without_integral + ki * integral;
+        // Only integrate if that doesn't push us
+        // further into saturation.
+        if output.abs() <= 1.0 || output.signum() != error.signum() {
+            self.integral = integral;
+        }
+        (without_integral + ki * self.integral).clamp(-1.0, 1.0)
+    }
+}
+
+// Drives both wheels at a requested speed. Targets
+// are kept in revolutions per second, like the
+// wheel speeds in the telemetry.
+pub struct WheelSpeedController
+{
+    left: Pid,
+    right: Pid,
+    target_left: f32,
+    target_right: f32,
+    last_sample: Option<Instant>,
+}
+
+impl WheelSpeedController {
+
+    pub fn new(gains: Gains) -> WheelSpeedController
+    {
+        WheelSpeedController{
+            left: Pid::new(gains),
+            right: Pid::new(gains),
+            target_left: 0.0,
+            target_right: 0.0,
+            last_sample: None,
+        }
+    }
+
+    pub fn set_target_revolutions(&mut self, left: f32, right: f32)
+    {
+        self.target_left = left;
+        self.target_right = right;
+    }
+
+    // Wheel speeds in cm/s, converted using
+    // the wheel diameter of the robot.
+    pub fn set_target_velocity(&mut self, robot: &TwoWheelRobot, left: f64, right: f64)
+    {
+        let circumference = PI * robot.wheeldiameter();
+        self.set_target_revolutions((left / circumference) as f32, (right / circumference) as f32);
+    }
+
+    pub fn target_revolutions(&self) -> (f32, f32)
+    {
+        (self.target_left, self.target_right)
+    }
+
+    pub fn reset(&mut self)
+    {
+        self.left.reset();
+        self.right.reset();
+        self.last_sample = None;
+    }
+
+    // Feed new telemetry. Returns the left and right
+    // wheel power to set, or None for anything but
+    // a wheel sample. Faults and shutdowns make the
+    // controllers start over, the battery status
+    // is just passed by.
+    pub fn update(&mut self, telemetry: &Telemetry) -> Option<(f32, f32)>
+    {
+        match telemetry {
+            Telemetry::Wheels(sample) => {
+                let dt = self.last_sample.map_or(0.0, |last| sample.when.saturating_duration_since(last).as_secs_f32());
+                self.last_sample = Some(sample.when);
+                let left = self.left.update(self.target_left, sample.left_speed, dt);
+                let right = self.right.update(self.target_right, sample.right_speed, dt);
+                Some((left, right))
+            },
+            Telemetry::Battery(_) => None,
+            Telemetry::Fault(_) | Telemetry::Shutdown => {
+                self.reset();
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::motor::{WheelSample, Fault};
+    use crate::battery::{BatteryStatus, Level};
+
+    fn sample(when: Instant, left_speed: f32, right_speed: f32) -> Telemetry
+    {
+        Telemetry::Wheels(WheelSample{
+            when,
+            voltage: 12.0,
+            left_steps: 0,
+            right_steps: 0,
+            steps_per_revolution: 360.0,
+            left_speed,
+            right_speed,
+            left_current: None,
+            right_current: None,
+        })
+    }
+
+    #[test]
+    fn proportional_and_feed_forward() {
+        let mut pid = Pid::new(Gains{kp: 0.5, ki: 0.0, kd: 0.0, kf: 0.25});
+        assert_eq!(pid.update(2.0, 2.0, 0.1), 0.5);
+        assert_eq!(pid.update(2.0, 1.0, 0.1), 1.0);
+        assert_eq!(pid.update(-2.0, -1.0, 0.1), -1.0);
+    }
+
+    #[test]
+    fn integral_accumulates() {
+        let mut pid = Pid::new(Gains{kp: 0.0, ki: 1.0, kd: 0.0, kf: 0.0});
+        assert_eq!(pid.update(1.0, 0.5, 0.1), 0.05);
+        assert!((pid.update(1.0, 0.5, 0.1) - 0.1).abs() < 1e-6);
+        pid.reset();
+        assert_eq!(pid.update(1.0, 0.5, 0.1), 0.05);
+    }
+
+    #[test]
+    fn derivative_reacts_to_changing_error() {
+        let mut pid = Pid::new(Gains{kp: 0.0, ki: 0.0, kd: 0.1, kf: 0.0});
+        assert_eq!(pid.update(1.0, 0.0, 0.1), 0.0);
+        assert!((pid.update(1.0, 0.5, 0.1) + 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn no_windup_while_saturated() {
+        let mut pid = Pid::new(Gains{kp: 0.0, ki: 1.0, kd: 0.0, kf: 0.0});
+        // the wheel is stuck, the output saturates
+        for _ in 0..100 {
+            assert!(pid.update(3.0, 0.0, 0.1) <= 1.0);
+        }
+        // once the wheel is free and too fast, the
+        // controller must back off right away
+        assert!(pid.update(3.0, 3.5, 0.1) < 1.0);
+    }
+
+    #[test]
+    fn reaches_target_with_weak_battery() {
+        // The motors only give 2.5 instead of the
+        // expected 3 rps at full power, and the left
+        // wheel is dragging.
+        let mut controller = WheelSpeedController::new(Gains::default());
+        controller.set_target_revolutions(1.5, -1.0);
+        let start = Instant::now();
+        let (mut speed1, mut speed2) = (0.0, 0.0);
+        for i in 0..200 {
+            let when = start + Duration::from_millis(100 * i);
+            let (left, right) = controller.update(&sample(when, speed1, speed2)).unwrap();
+            speed1 = 2.5 * left - 0.2;
+            speed2 = 2.5 * right;
+        }
+        assert!((speed1 - 1.5).abs() < 0.01, "{}", speed1);
+        assert!((speed2 + 1.0).abs() < 0.01, "{}", speed2);
+    }
+
+    #[test]
+    fn target_velocity_in_cm_per_second() {
+        let robot = TwoWheelRobot::new(23.5, 10.0);
+        let mut controller = WheelSpeedController::new(Gains::default());
+        controller.set_target_velocity(&robot, PI * 10.0, -PI * 5.0);
+        assert_eq!(controller.target_revolutions(), (1.0, -0.5));
+    }
+
+    #[test]
+    fn battery_status_keeps_the_integral() {
+        // The MD23 reports the battery along
+        // with every wheel sample.
+        let battery = Telemetry::Battery(BatteryStatus{
+            voltage: 12.0,
+            cell_voltage: 4.0,
+            level: Level::Normal,
+            state_of_charge: 80.0,
+            remaining: None,
+        });
+        let mut controller = WheelSpeedController::new(Gains{kp: 0.0, ki: 1.0, kd: 0.0, kf: 0.0});
+        controller.set_target_revolutions(1.0, 1.0);
+        let start = Instant::now();
+        let mut previous = 0.0;
+        for i in 0..5 {
+            let when = start + Duration::from_millis(100 * i);
+            let (left, right) = controller.update(&sample(when, 0.0, 0.0)).unwrap();
+            assert_eq!(left, right);
+            if i > 0 {
+                assert!(left > previous, "{} {}", left, previous);
+            }
+            previous = left;
+            assert_eq!(controller.update(&battery), None);
+        }
+        assert!((previous - 0.4).abs() < 1e-6);
+        // a fault starts over
+        controller.update(&Telemetry::Fault(Fault::CommandTimeout));
+        assert_eq!(controller.update(&sample(start, 0.0, 0.0)), Some((0.0, 0.0)));
+    }
+
+    #[test]
+    fn other_telemetry_yields_no_command() {
+        let mut controller = WheelSpeedController::new(Gains::default());
+        assert_eq!(controller.update(&Telemetry::Fault(Fault::LowVoltage)), None);
+    }
+}
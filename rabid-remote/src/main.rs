@@ -4,6 +4,7 @@ extern crate serde;
 use sdl2::pixels::Color;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use std::collections::HashMap;
 use std::time::Duration;
 use nanomsg::{Socket, Protocol, Error};
 
@@ -73,6 +74,11 @@ pub fn main() {
     canvas.clear();
     canvas.present();
     let mut event_pump = sdl_context.event_pump().unwrap();
+    // The robot stops if it doesn't hear from us, so the
+    // last value of each axis is repeated regularly, even
+    // if the stick isn't moved.
+    let mut axes = HashMap::new();
+    let keepalive_frames = 6;
     let mut i = 0;
     'running: loop {
         i = (i + 1) % 255;
@@ -89,6 +95,7 @@ pub fn main() {
                     // [-32768, 32767]. Let's simulate a very rough dead
                     // zone to ignore spurious events.
                     send_axis_value(&mut socket, axis_idx, val);
+                    axes.insert(axis_idx, val);
                 },
                 Event::JoyHatMotion{ hat_idx, state, .. } =>
                     println!("Hat {} moved to {:?}", hat_idx, state),
@@ -98,6 +105,11 @@ pub fn main() {
                 _ => {}
             }
         }
+        if i % keepalive_frames == 0 {
+            for (axis, value) in axes.iter() {
+                send_axis_value(&mut socket, *axis, *value);
+            }
+        }
         // The rest of the game loop goes here...
 
         canvas.present();